@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 pub static CORE_VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -26,6 +29,39 @@ pub enum InvocationError {
 
 pub type Result<T> = std::result::Result<T, InvocationError>;
 
+/// Options a plugin is configured with, as key-value pairs
+pub type PluginOptions = BTreeMap<String, String>;
+
+/// Element type of a `Tensor`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DataType {
+    Float,
+    Double,
+    Int8,
+    Int16,
+    Int32,
+    Int64,
+    UInt8,
+    UInt16,
+    Bool,
+    String,
+}
+
+/// An input or output node of a TensorFlow graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Node {
+    pub name: String,
+    pub id: i32,
+}
+
+/// A dense tensor, with its elements laid out in row-major order
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tensor {
+    pub dims: Vec<i64>,
+    pub data_type: DataType,
+    pub data: Vec<u8>,
+}
+
 /// The plugin API
 ///
 /// This is the set of functions supported by the vAccel API.
@@ -34,6 +70,10 @@ pub type Result<T> = std::result::Result<T, InvocationError>;
 /// of the functions. If a function is not implemented, it should
 /// return `InvocationError::NotImplemented`. Default implementations
 /// are provided to reflect that.
+///
+/// Plugins are called through the vtable of this trait, so new functions
+/// go at its end, and the version of this crate is bumped along with them:
+/// plugins built against another `CORE_VERSION` are refused.
 pub trait VaccelPlugin: Send + Sync {
     /// A function that returns a slice with the Functions supported by
     /// this plugin
@@ -48,6 +88,29 @@ pub trait VaccelPlugin: Send + Sync {
     fn tf_session_unload(&self, _model_id: u64) -> Result<()> {
         Err(InvocationError::NotImplemented)
     }
+
+    /// Run inference on a loaded TensorFlow model, feeding `inputs` and
+    /// returning the tensors of the `outputs` nodes
+    fn tf_session_run(
+        &self,
+        _model_id: u64,
+        _inputs: &[(Node, Tensor)],
+        _outputs: &[Node],
+    ) -> Result<Vec<Tensor>> {
+        Err(InvocationError::NotImplemented)
+    }
+
+    /// Set up the plugin with the options it is configured with. Called
+    /// once, right after the plugin is registered
+    fn init(&self, _options: &PluginOptions) -> Result<()> {
+        Ok(())
+    }
+
+    /// Release everything the plugin holds. Called once, when the agent
+    /// shuts down
+    fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Hash, Copy, Clone)]
@@ -70,6 +133,7 @@ pub struct PluginDeclaration {
     pub core_version: &'static str,
 
     /// A call-back function that registers the plugin with vAccel
+    #[allow(improper_ctypes_definitions)]
     pub register: unsafe extern "C" fn(&mut dyn PluginRegistrar),
 }
 
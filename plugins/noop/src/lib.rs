@@ -1,6 +1,8 @@
 use vaccel_plugins::export_plugin;
 use vaccel_plugins::VaccelPluginFunctions;
-use vaccel_plugins::{InvocationError, PluginRegistrar, Result, VaccelPlugin};
+use vaccel_plugins::{
+    InvocationError, Node, PluginOptions, PluginRegistrar, Result, Tensor, VaccelPlugin,
+};
 
 use env_logger::Env;
 use log::{debug, error};
@@ -10,6 +12,7 @@ pub struct Noop;
 const FUNCTIONS: &[VaccelPluginFunctions] = &[
     VaccelPluginFunctions::TFSessionLoad,
     VaccelPluginFunctions::TFSessionUnload,
+    VaccelPluginFunctions::TFSessionRun,
 ];
 
 impl VaccelPlugin for Noop {
@@ -46,11 +49,50 @@ impl VaccelPlugin for Noop {
             }
         }
     }
+
+    /// Echo the input tensors back, one for each requested output
+    fn tf_session_run(
+        &self,
+        model_id: u64,
+        inputs: &[(Node, Tensor)],
+        outputs: &[Node],
+    ) -> Result<Vec<Tensor>> {
+        match model_id {
+            0 => {
+                error!("[noop] Calling tf_session_run with invalid model id");
+                Err(InvocationError::InvalidArgument(
+                    "Unknown model id".to_owned(),
+                ))
+            }
+            _ => {
+                debug!("[noop] Running TF session for model {}", model_id);
+                Ok(inputs
+                    .iter()
+                    .take(outputs.len())
+                    .map(|(_, tensor)| tensor.clone())
+                    .collect())
+            }
+        }
+    }
+
+    fn init(&self, options: &PluginOptions) -> Result<()> {
+        for (key, value) in options {
+            debug!("[noop] Option {} = {}", key, value);
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        debug!("[noop] Shutting down");
+        Ok(())
+    }
 }
 
 export_plugin!(register);
 
+#[allow(improper_ctypes_definitions)]
 extern "C" fn register(registrar: &mut dyn PluginRegistrar) {
-    env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
+    // The plugin may be registered more than once in the same process
+    let _ = env_logger::Builder::from_env(Env::default().default_filter_or("debug")).try_init();
     registrar.register_plugin("vaccel-noop", Box::new(Noop));
 }
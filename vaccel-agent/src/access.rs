@@ -0,0 +1,50 @@
+use vaccel::server::Peer;
+
+/// Restricts which UNIX socket clients may connect to the agent, based on
+/// their peer credentials. An empty allowlist accepts everybody.
+#[derive(Debug, Clone, Default)]
+pub struct PeerAllowlist {
+    uids: Vec<u32>,
+    gids: Vec<u32>,
+}
+
+impl PeerAllowlist {
+    pub fn new(uids: Vec<u32>, gids: Vec<u32>) -> Self {
+        PeerAllowlist { uids, gids }
+    }
+
+    pub fn allows(&self, peer: &Peer) -> bool {
+        if self.uids.is_empty() && self.gids.is_empty() {
+            return true;
+        }
+
+        match peer {
+            Peer::Unix { uid, gid, .. } => self.uids.contains(uid) || self.gids.contains(gid),
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unix(uid: u32, gid: u32) -> Peer {
+        Peer::Unix {
+            uid,
+            gid,
+            pid: None,
+        }
+    }
+
+    #[test]
+    fn allowlist() {
+        assert!(PeerAllowlist::default().allows(&unix(1000, 1000)));
+
+        let allowlist = PeerAllowlist::new(vec![0], vec![100]);
+        assert!(allowlist.allows(&unix(0, 0)));
+        assert!(allowlist.allows(&unix(1000, 100)));
+        assert!(!allowlist.allows(&unix(1000, 1000)));
+        assert!(!allowlist.allows(&Peer::Local));
+    }
+}
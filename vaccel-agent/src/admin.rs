@@ -0,0 +1,155 @@
+use std::error::Error;
+
+use tarpc::context;
+use tarpc::serde_transport;
+use tokio::net::UnixListener;
+use tokio_serde::formats::Json;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use vaccel::admin;
+use vaccel::server::Server;
+
+use crate::cli::{AdminCli, AdminCommand};
+use crate::config::Config;
+
+/// Serve the administrative API to root and to the user running the agent
+pub async fn listen_admin(server: Server, listener: UnixListener, shutdown: CancellationToken) {
+    let uid = users::get_current_uid();
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Ok((stream, _)) => {
+                match stream.peer_cred() {
+                    Ok(cred) if cred.uid() == 0 || cred.uid() == uid => {
+                        debug!(uid = cred.uid(), "New admin client");
+                    }
+                    Ok(cred) => {
+                        warn!(uid = cred.uid(), "Rejecting unprivileged admin client");
+                        continue;
+                    }
+                    Err(e) => {
+                        error!("Could not read peer credentials: {}", e);
+                        continue;
+                    }
+                }
+
+                let transport = serde_transport::new(
+                    Framed::new(stream, LengthDelimitedCodec::new()),
+                    Json::default(),
+                );
+                tokio::spawn(server.clone().serve_admin(transport));
+            }
+            Err(e) => {
+                error!("Error while connecting to admin client: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+/// Run an admin subcommand against a running agent
+pub async fn run(cli: AdminCli, config: &Config) -> Result<(), Box<dyn Error>> {
+    let socket = cli
+        .socket
+        .or_else(|| config.admin.listen.clone())
+        .ok_or("No administrative socket given, nor configured")?;
+    let client = admin::connect(&socket)
+        .await
+        .map_err(|e| format!("{}: could not connect: {}", socket.display(), e))?;
+
+    match cli.command {
+        AdminCommand::Sessions => {
+            println!(
+                "{:<10} {:<12} {:<36} RUNDIR",
+                "SESSION", "CONNECTION", "PEER"
+            );
+            for session in client.sessions(context::current()).await? {
+                println!(
+                    "{:<10} {:<12} {:<36} {}",
+                    session.id,
+                    display(session.connection),
+                    session.peer.unwrap_or_else(|| "-".to_string()),
+                    session
+                        .rundir
+                        .map(|path| path.display().to_string())
+                        .unwrap_or_else(|| "-".to_string()),
+                );
+            }
+        }
+        AdminCommand::Resources => {
+            println!(
+                "{:<10} {:<24} {:<12} {:<12} LOADED",
+                "RESOURCE", "KIND", "BYTES", "CONNECTION"
+            );
+            for resource in client.resources(context::current()).await? {
+                println!(
+                    "{:<10} {:<24} {:<12} {:<12} {}",
+                    resource.id,
+                    resource.kind,
+                    resource.size,
+                    display(resource.connection),
+                    if resource.loaded { "yes" } else { "no" },
+                );
+            }
+        }
+        AdminCommand::Plugins => {
+            for plugin in client.plugins(context::current()).await? {
+                println!("{} ({})", plugin.name, plugin.path.display());
+                for function in plugin.functions {
+                    println!("    {}", function);
+                }
+            }
+        }
+        AdminCommand::Usage => {
+            println!(
+                "{:<32} {:<12} {:<24} {:<12} CALLS",
+                "PRINCIPAL", "SESSIONS", "RESOURCE BYTES", "MODELS"
+            );
+            for account in client.accounts(context::current()).await? {
+                let (quota, usage) = (account.quota, account.usage);
+                println!(
+                    "{:<32} {:<12} {:<24} {:<12} {}",
+                    account.principal.to_string(),
+                    ratio(usage.sessions, quota.max_sessions),
+                    ratio(usage.resource_bytes, quota.max_resource_bytes),
+                    ratio(usage.loaded_models, quota.max_loaded_models),
+                    ratio(usage.concurrent_calls, quota.max_concurrent_calls),
+                );
+            }
+        }
+        AdminCommand::DestroySession { session } => {
+            client
+                .destroy_session(context::current(), session)
+                .await?
+                .map_err(|e| format!("Could not destroy session {}: {}", session, e))?;
+        }
+        AdminCommand::ReloadPlugins => {
+            client
+                .reload_plugins(context::current())
+                .await?
+                .map_err(|e| format!("Could not reload plugins: {}", e))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn display(id: Option<u64>) -> String {
+    id.map(|id| id.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Usage against its limit, as `<used>/<limit>`
+fn ratio(used: usize, limit: Option<usize>) -> String {
+    match limit {
+        Some(limit) => format!("{}/{}", used, limit),
+        None => format!("{}/-", used),
+    }
+}
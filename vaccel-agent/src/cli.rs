@@ -1,11 +1,168 @@
+use std::path::PathBuf;
+
 use structopt::StructOpt;
 
+use crate::config::LogFormat;
+
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "vAccel agent",
     about = "A vAccel agent that handles RPC acceleration requests"
 )]
 pub struct AgentCli {
-    #[structopt(short = "a", long = "server-address")]
-    pub uri: String,
+    /// Configuration file. Flags given on the command line override its
+    /// values
+    #[structopt(short = "c", long = "config", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Address to listen on: a UNIX socket path, `unix://<path>`,
+    /// `vsock://<cid>:<port>` or `tcp://<host>:<port>`. May be repeated.
+    /// Ignored when sockets are passed by systemd
+    #[structopt(short = "a", long = "server-address", number_of_values = 1)]
+    pub uris: Vec<String>,
+
+    /// Permissions of the UNIX sockets, in octal (e.g. 0660)
+    #[structopt(long = "socket-mode")]
+    pub socket_mode: Option<String>,
+
+    /// User owning the UNIX sockets, by name or id
+    #[structopt(long = "socket-owner")]
+    pub socket_owner: Option<String>,
+
+    /// Group owning the UNIX sockets, by name or id
+    #[structopt(long = "socket-group")]
+    pub socket_group: Option<String>,
+
+    /// Directory under which the agent creates its run directory. Defaults
+    /// to `/run/user/<uid>/vaccel`, `$XDG_RUNTIME_DIR/vaccel` or
+    /// `/tmp/vaccel-<uid>`, whichever is available first
+    #[structopt(long = "rundir", parse(from_os_str))]
+    pub rundir: Option<PathBuf>,
+
+    /// Plugin library to load. May be repeated
+    #[structopt(long = "plugin", number_of_values = 1, parse(from_os_str))]
+    pub plugins: Vec<PathBuf>,
+
+    /// Default log level, overridden by `RUST_LOG`
+    #[structopt(long = "log-level")]
+    pub log_level: Option<String>,
+
+    /// Format of the log lines: `text` or `json`
+    #[structopt(long = "log-format", possible_values = &["text", "json"])]
+    pub log_format: Option<LogFormat>,
+
+    /// PEM certificate chain used to serve TLS on TCP addresses
+    #[structopt(long = "tls-cert", requires = "tls-key", parse(from_os_str))]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the TLS certificate
+    #[structopt(long = "tls-key", requires = "tls-cert", parse(from_os_str))]
+    pub tls_key: Option<PathBuf>,
+
+    /// PEM bundle of CAs used to authenticate client certificates. Enables
+    /// mutual TLS
+    #[structopt(long = "tls-client-ca", requires = "tls-cert", parse(from_os_str))]
+    pub tls_client_ca: Option<PathBuf>,
+
+    /// Allow UNIX socket clients running as this user id. May be repeated
+    #[structopt(long = "allow-uid", number_of_values = 1)]
+    pub allow_uids: Vec<u32>,
+
+    /// Allow UNIX socket clients running with this group id. May be repeated
+    #[structopt(long = "allow-gid", number_of_values = 1)]
+    pub allow_gids: Vec<u32>,
+
+    /// File with the tokens clients must authenticate with. When not given,
+    /// clients are not required to authenticate
+    #[structopt(long = "token-file", parse(from_os_str))]
+    pub token_file: Option<PathBuf>,
+
+    /// Maximum number of concurrent client connections
+    #[structopt(long = "max-connections")]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of requests in flight on a single connection
+    #[structopt(long = "max-requests-per-connection")]
+    pub max_requests_per_connection: Option<usize>,
+
+    /// Maximum number of requests in flight across all connections
+    #[structopt(long = "max-requests")]
+    pub max_requests: Option<usize>,
+
+    /// Maximum number of sessions a tenant may hold
+    #[structopt(long = "quota-sessions")]
+    pub quota_sessions: Option<usize>,
+
+    /// Maximum number of bytes the resources of a tenant may hold. Resources
+    /// referring to files on the host are then refused.
+    #[structopt(long = "quota-resource-bytes")]
+    pub quota_resource_bytes: Option<usize>,
+
+    /// Maximum number of models a tenant may have loaded
+    #[structopt(long = "quota-loaded-models")]
+    pub quota_loaded_models: Option<usize>,
+
+    /// Maximum number of requests and jobs a tenant may have in flight
+    #[structopt(long = "quota-concurrent-calls")]
+    pub quota_concurrent_calls: Option<usize>,
+
+    /// Address of the HTTP endpoint serving Prometheus metrics at
+    /// `/metrics`, as `<host>:<port>`
+    #[structopt(long = "metrics-address")]
+    pub metrics_address: Option<String>,
+
+    /// Seconds to wait for requests in flight when shutting down
+    #[structopt(long = "shutdown-timeout")]
+    pub shutdown_timeout: Option<u64>,
+
+    /// UNIX socket serving the administrative API. Only root and the user
+    /// running the agent may connect to it
+    #[structopt(long = "admin-address", parse(from_os_str))]
+    pub admin_address: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Inspect and manage a running agent
+    Admin(AdminCli),
+    /// Remove the run directories no live agent holds under the root
+    /// directory, then exit
+    ReclaimRundirs {
+        /// Also remove the run directories of agents predating lock files.
+        /// Only use once no such agent runs anymore
+        #[structopt(long = "legacy")]
+        legacy: bool,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub struct AdminCli {
+    /// Administrative socket of the agent. Defaults to the one of the
+    /// configuration file
+    #[structopt(short = "s", long = "socket", parse(from_os_str))]
+    pub socket: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: AdminCommand,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum AdminCommand {
+    /// List sessions, with their owners and rundirs
+    Sessions,
+    /// List registered resources and whether they are loaded
+    Resources,
+    /// List loaded plugins and the functions they implement
+    Plugins,
+    /// Show what every tenant holds, against its quota
+    Usage,
+    /// Destroy a session, whoever owns it
+    DestroySession { session: u64 },
+    /// Load all plugins again. Clients have to load their models again.
+    /// Plugin libraries are not read again from disk: restart the agent to
+    /// upgrade them.
+    ReloadPlugins,
 }
@@ -0,0 +1,590 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use serde::Deserialize;
+use tracing::level_filters::LevelFilter;
+
+use vaccel::client::Endpoint;
+use vaccel::quota::{Principal, Quota, Quotas};
+use vaccel::server::{Limits, PluginOptions};
+
+use crate::cli::AgentCli;
+use crate::socket::SocketPermissions;
+
+/// Configuration of the agent, read from a TOML file:
+///
+/// ```toml
+/// listen = [
+///     "unix:///run/vaccel/vaccel.sock",
+///     "vsock://4294967295:2048",
+///     "tcp://0.0.0.0:2048",
+/// ]
+/// rundir = "/run/vaccel"
+/// shutdown_timeout = 10
+///
+/// [socket]
+/// mode = "0660"
+/// group = "kvm"
+///
+/// [log]
+/// level = "info"
+/// format = "json"
+///
+/// [metrics]
+/// listen = "127.0.0.1:9100"
+///
+/// [admin]
+/// listen = "/run/vaccel/admin.sock"
+///
+/// [auth]
+/// token_file = "/etc/vaccel/tokens"
+/// allow_uids = [1000]
+///
+/// [tls]
+/// cert = "/etc/vaccel/agent.pem"
+/// key = "/etc/vaccel/agent.key"
+///
+/// [limits]
+/// max_connections = 64
+///
+/// [quotas.default]
+/// max_sessions = 16
+/// max_resource_bytes = 1073741824
+///
+/// [quotas.cid.3]
+/// max_sessions = 64
+///
+/// [quotas.tenant.guest-1]
+/// max_loaded_models = 8
+///
+/// [[plugins]]
+/// path = "/usr/lib/libvaccel_noop.so"
+/// options = { verbose = true }
+/// ```
+///
+/// Command line flags take precedence over the values of the file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses to listen on
+    pub listen: Vec<String>,
+    pub socket: SocketConfig,
+    /// Directory under which the agent creates its run directory
+    pub rundir: Option<PathBuf>,
+    /// Seconds to wait for requests in flight when shutting down
+    pub shutdown_timeout: Option<u64>,
+    pub plugins: Vec<PluginConfig>,
+    pub limits: LimitsConfig,
+    pub quotas: QuotasConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub admin: AdminConfig,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketConfig {
+    /// Permissions of the UNIX sockets, in octal
+    pub mode: Option<String>,
+    /// User owning the UNIX sockets, by name or id
+    pub owner: Option<String>,
+    /// Group owning the UNIX sockets, by name or id
+    pub group: Option<String>,
+}
+
+impl SocketConfig {
+    pub fn permissions(&self) -> Result<SocketPermissions, Vec<String>> {
+        let mut errors = Vec::new();
+
+        let mode = self.mode.as_ref().and_then(|mode| {
+            let digits = mode.trim_start_matches("0o");
+            match u32::from_str_radix(digits, 8) {
+                Ok(mode) if mode <= 0o7777 => Some(mode),
+                _ => {
+                    errors.push(format!("socket.mode: invalid mode '{}'", mode));
+                    None
+                }
+            }
+        });
+
+        let uid = self.owner.as_ref().and_then(|owner| {
+            let uid = owner
+                .parse()
+                .ok()
+                .or_else(|| users::get_user_by_name(owner).map(|user| user.uid()));
+            if uid.is_none() {
+                errors.push(format!("socket.owner: unknown user '{}'", owner));
+            }
+            uid
+        });
+
+        let gid = self.group.as_ref().and_then(|group| {
+            let gid = group
+                .parse()
+                .ok()
+                .or_else(|| users::get_group_by_name(group).map(|group| group.gid()));
+            if gid.is_none() {
+                errors.push(format!("socket.group: unknown group '{}'", group));
+            }
+            gid
+        });
+
+        if errors.is_empty() {
+            Ok(SocketPermissions { mode, uid, gid })
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub options: BTreeMap<String, toml::Value>,
+}
+
+impl PluginConfig {
+    pub fn new(path: PathBuf) -> Self {
+        PluginConfig {
+            path,
+            options: BTreeMap::new(),
+        }
+    }
+
+    /// The options of the plugin, as passed to it
+    pub fn options(&self) -> PluginOptions {
+        self.options
+            .iter()
+            .map(|(key, value)| {
+                let value = match value {
+                    toml::Value::String(value) => value.clone(),
+                    value => value.to_string(),
+                };
+                (key.clone(), value)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections: Option<usize>,
+    pub max_requests_per_connection: Option<usize>,
+    pub max_requests: Option<usize>,
+}
+
+impl LimitsConfig {
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_connections: self.max_connections,
+            max_requests_per_connection: self.max_requests_per_connection,
+            max_requests: self.max_requests,
+        }
+    }
+}
+
+/// Quotas enforced on every tenant, and overrides for specific user ids,
+/// vsock context ids and authenticated tenants. Limits an override leaves
+/// unset are taken from the default quota.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotasConfig {
+    pub default: QuotaConfig,
+    pub uid: BTreeMap<String, QuotaConfig>,
+    pub cid: BTreeMap<String, QuotaConfig>,
+    pub tenant: BTreeMap<String, QuotaConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QuotaConfig {
+    pub max_sessions: Option<usize>,
+    pub max_resource_bytes: Option<usize>,
+    pub max_loaded_models: Option<usize>,
+    pub max_concurrent_calls: Option<usize>,
+}
+
+impl QuotaConfig {
+    /// The quota, with the limits left unset taken from `fallback`
+    fn quota(&self, fallback: &Quota) -> Quota {
+        Quota {
+            max_sessions: self.max_sessions.or(fallback.max_sessions),
+            max_resource_bytes: self.max_resource_bytes.or(fallback.max_resource_bytes),
+            max_loaded_models: self.max_loaded_models.or(fallback.max_loaded_models),
+            max_concurrent_calls: self.max_concurrent_calls.or(fallback.max_concurrent_calls),
+        }
+    }
+}
+
+impl QuotasConfig {
+    /// Overrides of the default quota, by principal, or the keys that are
+    /// not valid ids
+    fn overrides(&self) -> (Vec<(Principal, &QuotaConfig)>, Vec<String>) {
+        let mut overrides = Vec::new();
+        let mut errors = Vec::new();
+
+        for (table, quotas) in [("uid", &self.uid), ("cid", &self.cid)].iter() {
+            for (id, quota) in quotas.iter() {
+                match id.parse() {
+                    Ok(id) if *table == "uid" => overrides.push((Principal::Uid(id), quota)),
+                    Ok(id) => overrides.push((Principal::Cid(id), quota)),
+                    Err(_) => errors.push(format!("quotas.{}: invalid id '{}'", table, id)),
+                }
+            }
+        }
+        for (name, quota) in self.tenant.iter() {
+            overrides.push((Principal::Tenant(name.clone()), quota));
+        }
+
+        (overrides, errors)
+    }
+
+    pub fn quotas(&self) -> Quotas {
+        let default = self.default.quota(&Quota::default());
+        let (overrides, _) = self.overrides();
+
+        overrides.into_iter().fold(
+            Quotas::new(default.clone()),
+            |quotas, (principal, quota)| quotas.set(principal, quota.quota(&default)),
+        )
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Default log level, overridden by `RUST_LOG`
+    pub level: Option<String>,
+    pub format: LogFormat,
+}
+
+/// How log lines are written
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines
+    #[default]
+    Text,
+    /// One JSON object per line, carrying the fields of the enclosing spans
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}'", format)),
+        }
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address of the HTTP endpoint serving Prometheus metrics
+    pub listen: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminConfig {
+    /// UNIX socket serving the administrative API
+    pub listen: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// File with the tokens clients must authenticate with
+    pub token_file: Option<PathBuf>,
+    /// User ids allowed to connect over UNIX sockets
+    pub allow_uids: Vec<u32>,
+    /// Group ids allowed to connect over UNIX sockets
+    pub allow_gids: Vec<u32>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    pub cert: Option<PathBuf>,
+    pub key: Option<PathBuf>,
+    pub client_ca: Option<PathBuf>,
+}
+
+impl Config {
+    /// Default time to wait for requests in flight when shutting down
+    pub const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("{}: could not read configuration: {}", path.display(), e))?;
+
+        toml::from_str(&contents)
+            .map_err(|e| format!("{}: invalid configuration: {}", path.display(), e).into())
+    }
+
+    /// Override the values of the configuration with the flags given on the
+    /// command line
+    pub fn merge(mut self, cli: AgentCli) -> Self {
+        if !cli.uris.is_empty() {
+            self.listen = cli.uris;
+        }
+        if cli.socket_mode.is_some() {
+            self.socket.mode = cli.socket_mode;
+        }
+        if cli.socket_owner.is_some() {
+            self.socket.owner = cli.socket_owner;
+        }
+        if cli.socket_group.is_some() {
+            self.socket.group = cli.socket_group;
+        }
+        if cli.rundir.is_some() {
+            self.rundir = cli.rundir;
+        }
+        if !cli.plugins.is_empty() {
+            self.plugins = cli.plugins.into_iter().map(PluginConfig::new).collect();
+        }
+        if cli.metrics_address.is_some() {
+            self.metrics.listen = cli.metrics_address;
+        }
+        if cli.admin_address.is_some() {
+            self.admin.listen = cli.admin_address;
+        }
+        if cli.shutdown_timeout.is_some() {
+            self.shutdown_timeout = cli.shutdown_timeout;
+        }
+        if cli.log_level.is_some() {
+            self.log.level = cli.log_level;
+        }
+        if let Some(format) = cli.log_format {
+            self.log.format = format;
+        }
+
+        if cli.max_connections.is_some() {
+            self.limits.max_connections = cli.max_connections;
+        }
+        if cli.max_requests_per_connection.is_some() {
+            self.limits.max_requests_per_connection = cli.max_requests_per_connection;
+        }
+        if cli.max_requests.is_some() {
+            self.limits.max_requests = cli.max_requests;
+        }
+
+        let quota = &mut self.quotas.default;
+        if cli.quota_sessions.is_some() {
+            quota.max_sessions = cli.quota_sessions;
+        }
+        if cli.quota_resource_bytes.is_some() {
+            quota.max_resource_bytes = cli.quota_resource_bytes;
+        }
+        if cli.quota_loaded_models.is_some() {
+            quota.max_loaded_models = cli.quota_loaded_models;
+        }
+        if cli.quota_concurrent_calls.is_some() {
+            quota.max_concurrent_calls = cli.quota_concurrent_calls;
+        }
+
+        if cli.token_file.is_some() {
+            self.auth.token_file = cli.token_file;
+        }
+        if !cli.allow_uids.is_empty() {
+            self.auth.allow_uids = cli.allow_uids;
+        }
+        if !cli.allow_gids.is_empty() {
+            self.auth.allow_gids = cli.allow_gids;
+        }
+
+        if cli.tls_cert.is_some() {
+            self.tls.cert = cli.tls_cert;
+            self.tls.key = cli.tls_key;
+        }
+        if cli.tls_client_ca.is_some() {
+            self.tls.client_ca = cli.tls_client_ca;
+        }
+
+        self
+    }
+
+    /// Time to wait for requests in flight when shutting down
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+            .map(Duration::from_secs)
+            .unwrap_or(Self::DEFAULT_SHUTDOWN_TIMEOUT)
+    }
+
+    /// Check the configuration, returning every problem found. Sockets
+    /// passed by systemd, when `socket_activated`, stand in for the
+    /// addresses to listen on.
+    pub fn validate(&self, socket_activated: bool) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+
+        if self.listen.is_empty() && !socket_activated {
+            errors.push("listen: no address to listen on".to_string());
+        }
+
+        let mut tcp = socket_activated;
+        for uri in self.listen.iter() {
+            match uri.parse() {
+                Ok(Endpoint::Unix(_)) | Ok(Endpoint::Vsock(..)) => {}
+                Ok(Endpoint::Tcp(_)) => tcp = true,
+                _ => errors.push(format!("listen: cannot listen on '{}'", uri)),
+            }
+        }
+
+        if let Err(e) = self.socket.permissions() {
+            errors.extend(e);
+        }
+
+        for plugin in self.plugins.iter() {
+            check_file("plugins.path", &plugin.path, &mut errors);
+        }
+
+        let limits = [
+            ("limits.max_connections", self.limits.max_connections),
+            (
+                "limits.max_requests_per_connection",
+                self.limits.max_requests_per_connection,
+            ),
+            ("limits.max_requests", self.limits.max_requests),
+        ];
+        for (name, limit) in limits.iter() {
+            if *limit == Some(0) {
+                errors.push(format!("{}: must be at least 1", name));
+            }
+        }
+
+        errors.extend(self.quotas.overrides().1);
+
+        if let Some(ref level) = self.log.level {
+            if level.parse::<LevelFilter>().is_err() {
+                errors.push(format!("log.level: unknown level '{}'", level));
+            }
+        }
+
+        if let Some(ref addr) = self.metrics.listen {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(format!("metrics.listen: invalid address '{}'", addr));
+            }
+        }
+
+        if let Some(ref path) = self.auth.token_file {
+            check_file("auth.token_file", path, &mut errors);
+        }
+
+        match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => {
+                check_file("tls.cert", cert, &mut errors);
+                check_file("tls.key", key, &mut errors);
+                if !tcp {
+                    errors.push("tls: no TCP address to serve TLS on".to_string());
+                }
+            }
+            (None, None) => {
+                if self.tls.client_ca.is_some() {
+                    errors.push("tls.client_ca: requires tls.cert and tls.key".to_string());
+                }
+            }
+            _ => errors.push("tls: cert and key must be given together".to_string()),
+        }
+        if let Some(ref path) = self.tls.client_ca {
+            check_file("tls.client_ca", path, &mut errors);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+fn check_file(name: &str, path: &Path, errors: &mut Vec<String>) {
+    if !path.is_file() {
+        errors.push(format!("{}: {} is not a file", name, path.display()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_and_validate() {
+        let config: Config = toml::from_str(
+            r#"
+            listen = ["unix:///tmp/vaccel.sock", "vsock://2:2048", "local"]
+
+            [socket]
+            mode = "0o660"
+            group = "0"
+
+            [limits]
+            max_connections = 0
+
+            [quotas.default]
+            max_sessions = 4
+            max_loaded_models = 1
+
+            [quotas.cid.3]
+            max_sessions = 8
+
+            [quotas.uid.root]
+            max_sessions = 1
+
+            [metrics]
+            listen = "localhost"
+
+            [tls]
+            key = "/nonexistent/agent.key"
+
+            [[plugins]]
+            path = "/nonexistent/libvaccel_noop.so"
+            options = { verbose = true, name = "noop" }
+            "#,
+        )
+        .expect("Could not parse configuration");
+
+        let options = config.plugins[0].options();
+        assert_eq!(options["verbose"], "true");
+        assert_eq!(options["name"], "noop");
+
+        let quotas = config.quotas.quotas();
+        let cid = quotas.get(&Principal::Cid(3));
+        assert_eq!(cid.max_sessions, Some(8));
+        assert_eq!(cid.max_loaded_models, Some(1));
+        assert_eq!(quotas.get(&Principal::Cid(4)).max_sessions, Some(4));
+
+        let permissions = config.socket.permissions().unwrap();
+        assert_eq!(permissions.mode, Some(0o660));
+        assert_eq!(permissions.uid, None);
+        assert_eq!(permissions.gid, Some(0));
+
+        let errors = config.validate(false).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                "listen: cannot listen on 'local'",
+                "plugins.path: /nonexistent/libvaccel_noop.so is not a file",
+                "limits.max_connections: must be at least 1",
+                "quotas.uid: invalid id 'root'",
+                "metrics.listen: invalid address 'localhost'",
+                "tls: cert and key must be given together",
+            ]
+        );
+
+        assert!(toml::from_str::<Config>("listen = []\nunknown = 1\n").is_err());
+    }
+}
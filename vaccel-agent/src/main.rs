@@ -1,43 +1,155 @@
 use std::error::Error;
-use std::path::PathBuf;
+use std::fs;
+use std::process;
+use std::time::Duration;
 
 use structopt::StructOpt;
 
-use tokio::net::UnixListener;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
 use tokio_serde::formats::Json;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+use tokio_vsock::{SockAddr, VsockListener};
 
 use tarpc::serde_transport;
-use tarpc::server::{BaseChannel, Channel};
 
-use vaccel::server::{Server, VaccelAPI};
+use vaccel::auth::TokenStore;
+use vaccel::client::Endpoint;
+use vaccel::rundir;
+use vaccel::server::{Peer, Server, ServerBuilder};
+use vaccel::tls::{ServerTlsConfig, TlsAcceptor};
 
-use log::{debug, error};
-
-extern crate signal_hook;
+use tracing::{debug, error, info, warn};
+use tracing_subscriber::EnvFilter;
 
+mod access;
+mod admin;
 mod cli;
+mod config;
+mod metrics;
+mod socket;
+mod systemd;
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
+use access::PeerAllowlist;
+use cli::Command;
+use config::{Config, LogFormat};
+use socket::{Listener, SocketPermissions};
 
-    let cli = cli::AgentCli::from_args();
+/// Time a TCP client has to complete the TLS handshake
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serve the vAccel API over an accepted client stream
+fn serve<S>(server: &Server, stream: S, peer: Peer)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let transport = serde_transport::new(
+        Framed::new(stream, LengthDelimitedCodec::new()),
+        Json::default(),
+    );
+
+    tokio::spawn(server.clone().serve(transport, peer));
+}
 
-    debug!("Opening API socket at {}", cli.uri);
-    let unix_socket_path = PathBuf::from(cli.uri);
-    let listener = UnixListener::bind(unix_socket_path)?;
+async fn listen_unix(
+    server: Server,
+    listener: UnixListener,
+    allowlist: PeerAllowlist,
+    shutdown: CancellationToken,
+) {
     loop {
-        match listener.accept().await {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+
+        match accepted {
             Ok((stream, addr)) => {
                 debug!("New client at {:?}", addr);
-                let transport = serde_transport::new(
-                    Framed::new(stream, LengthDelimitedCodec::new()),
-                    Json::default(),
-                );
+                let peer = match stream.peer_cred() {
+                    Ok(cred) => Peer::Unix {
+                        uid: cred.uid(),
+                        gid: cred.gid(),
+                        pid: cred.pid(),
+                    },
+                    Err(e) => {
+                        error!("Could not read peer credentials: {}", e);
+                        continue;
+                    }
+                };
+
+                if !allowlist.allows(&peer) {
+                    warn!("Rejecting connection from {}", peer);
+                    continue;
+                }
+
+                serve(&server, stream, peer);
+            }
+            Err(e) => {
+                error!("Error while connecting to client: {}", e);
+                break;
+            }
+        }
+    }
+}
+
+async fn listen_tcp(
+    server: Server,
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    handshake_timeout: Duration,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Ok((stream, addr)) => {
+                debug!("New client at {}", addr);
+                match tls {
+                    None => serve(&server, stream, Peer::Tcp(addr)),
+                    Some(ref acceptor) => {
+                        // Do not hold up the accept loop while handshaking
+                        let acceptor = acceptor.clone();
+                        let server = server.clone();
+                        tokio::spawn(async move {
+                            let handshake = acceptor.accept(stream);
+                            match tokio::time::timeout(handshake_timeout, handshake).await {
+                                Ok(Ok(stream)) => serve(&server, stream, Peer::Tcp(addr)),
+                                Ok(Err(e)) => error!("TLS handshake with {} failed: {}", addr, e),
+                                Err(_) => warn!("TLS handshake with {} timed out", addr),
+                            }
+                        });
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Error while connecting to client: {}", e);
+                break;
+            }
+        }
+    }
+}
 
-                let server = BaseChannel::with_defaults(transport);
-                tokio::spawn(server.execute(Server::new()?.serve()));
+async fn listen_vsock(server: Server, mut listener: VsockListener, shutdown: CancellationToken) {
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.cancelled() => break,
+        };
+
+        match accepted {
+            Ok((stream, addr)) => {
+                debug!("New client at {:?}", addr);
+                let cid = match addr {
+                    SockAddr::Vsock(addr) => addr.cid(),
+                    _ => continue,
+                };
+                serve(&server, stream, Peer::Vsock { cid });
             }
             Err(e) => {
                 error!("Error while connecting to client: {}", e);
@@ -45,6 +157,228 @@ async fn main() -> Result<(), Box<dyn Error>> {
             }
         }
     }
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let mut cli = cli::AgentCli::from_args();
+    let command = cli.command.take();
+    let config = match cli.config {
+        Some(ref path) => Config::load(path)?,
+        None => Config::default(),
+    }
+    .merge(cli);
+
+    let level = config.log.level.as_deref().unwrap_or("error");
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    let logger = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+    match config.log.format {
+        LogFormat::Text => logger.init(),
+        LogFormat::Json => logger.json().init(),
+    }
+
+    match command {
+        Some(Command::Admin(cli)) => return admin::run(cli, &config).await,
+        Some(Command::ReclaimRundirs { legacy }) => {
+            let root = config.rundir.clone().unwrap_or_else(rundir::default_root);
+            for path in rundir::reclaim_stale(&root, legacy)? {
+                println!("{}", path.display());
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // Sockets passed by systemd take the place of the configured addresses
+    let mut listeners = systemd::listen_fds()?;
+
+    if let Err(errors) = config.validate(!listeners.is_empty()) {
+        eprintln!("Invalid configuration:");
+        for e in errors {
+            eprintln!("  - {}", e);
+        }
+        process::exit(1);
+    }
+
+    let tls = match (&config.tls.cert, &config.tls.key) {
+        (Some(cert), Some(key)) => {
+            let mut tls = ServerTlsConfig::new(cert.clone(), key.clone());
+            if let Some(ref ca) = config.tls.client_ca {
+                tls = tls.client_ca(ca.clone());
+            }
+            Some(tls.acceptor()?)
+        }
+        _ => None,
+    };
+
+    let mut builder = ServerBuilder::new()
+        .limits(config.limits.limits())
+        .quotas(config.quotas.quotas());
+    if let Some(ref root) = config.rundir {
+        builder = builder.rundir(root.clone());
+    }
+    for plugin in config.plugins.iter() {
+        builder = builder.plugin(plugin.path.clone(), plugin.options());
+    }
+    if let Some(ref path) = config.auth.token_file {
+        let tokens = TokenStore::load(path)
+            .map_err(|e| format!("{}: could not load tokens: {}", path.display(), e))?;
+        builder = builder.tokens(tokens);
+    }
+    let server = builder.build()?;
+
+    // Stop accepting clients on SIGINT and SIGTERM
+    let shutdown_timeout = config.shutdown_timeout();
+    let shutdown = CancellationToken::new();
+    let token = shutdown.clone();
+    ctrlc::set_handler(move || token.cancel())?;
+
+    let permissions = config.socket.permissions().map_err(|e| e.join(", "))?;
+    let mut sockets = Vec::new();
+    if listeners.is_empty() {
+        for uri in config.listen.iter() {
+            debug!("Opening API socket at {}", uri);
+            let listener = match uri.parse()? {
+                Endpoint::Unix(path) => {
+                    let listener = socket::bind_unix(&path, &permissions)
+                        .map_err(|e| format!("{}: {}", path.display(), e))?;
+                    sockets.push(path);
+                    Listener::Unix(listener)
+                }
+                Endpoint::Tcp(addr) => Listener::Tcp(TcpListener::bind(addr).await?),
+                Endpoint::Vsock(cid, port) => Listener::Vsock(VsockListener::bind(cid, port)?),
+                endpoint => return Err(format!("Cannot listen on {:?}", endpoint).into()),
+            };
+            listeners.push(listener);
+        }
+    } else {
+        info!("Using {} sockets passed by systemd", listeners.len());
+    }
+
+    let allowlist = PeerAllowlist::new(config.auth.allow_uids, config.auth.allow_gids);
+    let mut tasks: Vec<_> = listeners
+        .into_iter()
+        .map(|listener| match listener {
+            Listener::Unix(listener) => tokio::spawn(listen_unix(
+                server.clone(),
+                listener,
+                allowlist.clone(),
+                shutdown.clone(),
+            )),
+            Listener::Tcp(listener) => tokio::spawn(listen_tcp(
+                server.clone(),
+                listener,
+                tls.clone(),
+                TLS_HANDSHAKE_TIMEOUT,
+                shutdown.clone(),
+            )),
+            Listener::Vsock(listener) => {
+                tokio::spawn(listen_vsock(server.clone(), listener, shutdown.clone()))
+            }
+        })
+        .collect();
+
+    if let Some(ref path) = config.admin.listen {
+        debug!("Opening admin socket at {}", path.display());
+        let permissions = SocketPermissions {
+            mode: Some(0o600),
+            ..Default::default()
+        };
+        let listener = socket::bind_unix(path, &permissions)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        sockets.push(path.clone());
+        tasks.push(tokio::spawn(admin::listen_admin(
+            server.clone(),
+            listener,
+            shutdown.clone(),
+        )));
+    }
+
+    let metrics = match config.metrics.listen {
+        Some(ref addr) => {
+            debug!("Serving metrics at {}", addr);
+            let listener = std::net::TcpListener::bind(addr)
+                .map_err(|e| format!("{}: could not serve metrics: {}", addr, e))?;
+            Some(tokio::spawn(metrics::serve_metrics(
+                server.clone(),
+                listener,
+                shutdown.clone(),
+            )))
+        }
+        None => None,
+    };
+
+    if let Err(e) = systemd::notify("READY=1") {
+        warn!("Could not notify readiness: {}", e);
+    }
+
+    for task in tasks {
+        task.await?;
+    }
+    if let Some(metrics) = metrics {
+        if let Err(e) = metrics.await? {
+            error!("Metrics endpoint failed: {}", e);
+        }
+    }
+
+    if let Err(e) = systemd::notify("STOPPING=1") {
+        warn!("Could not notify shutdown: {}", e);
+    }
+    info!("Shutting down");
+    server.shutdown(shutdown_timeout).await;
+    for path in sockets {
+        if let Err(e) = fs::remove_file(&path) {
+            warn!("Could not remove {}: {}", path.display(), e);
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpStream;
+
+    #[tokio::test]
+    async fn tls_handshake_timeout() {
+        let dir = std::env::temp_dir().join(format!("vaccel-agent-tls-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        fs::write(dir.join("server.pem"), certified.cert.pem()).unwrap();
+        fs::write(
+            dir.join("server.key"),
+            certified.signing_key.serialize_pem(),
+        )
+        .unwrap();
+        let acceptor = ServerTlsConfig::new(dir.join("server.pem"), dir.join("server.key"))
+            .acceptor()
+            .unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        tokio::spawn(listen_tcp(
+            Server::new().unwrap(),
+            listener,
+            Some(acceptor),
+            Duration::from_millis(100),
+            shutdown.clone(),
+        ));
+
+        // A client that never starts the handshake is hung up on
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), stream.read(&mut buf))
+            .await
+            .expect("Handshake did not time out");
+        assert!(matches!(read, Ok(0) | Err(_)));
+
+        shutdown.cancel();
+    }
+}
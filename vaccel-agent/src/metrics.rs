@@ -0,0 +1,83 @@
+use std::convert::Infallible;
+use std::net::TcpListener;
+
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use tokio_util::sync::CancellationToken;
+
+use vaccel::server::Server;
+
+/// Serve the metrics of `server` over HTTP at `/metrics`, until `shutdown`
+pub async fn serve_metrics(
+    server: Server,
+    listener: TcpListener,
+    shutdown: CancellationToken,
+) -> hyper::Result<()> {
+    let make_service = make_service_fn(move |_| {
+        let server = server.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = respond(&server, request);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    hyper::Server::from_tcp(listener)?
+        .serve(make_service)
+        .with_graceful_shutdown(async move { shutdown.cancelled().await })
+        .await
+}
+
+fn respond(server: &Server, request: Request<Body>) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+            *response.body_mut() = Body::from(server.metrics());
+        }
+        _ => *response.status_mut() = StatusCode::NOT_FOUND,
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    async fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nConnection: close\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint() {
+        let server = Server::new().expect("Could not create Server");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(serve_metrics(server, listener, shutdown.clone()));
+
+        let response = get(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("vaccel_sessions 0"));
+
+        let response = get(addr, "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found"));
+
+        shutdown.cancel();
+        task.await.unwrap().unwrap();
+    }
+}
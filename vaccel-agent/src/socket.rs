@@ -0,0 +1,131 @@
+use std::fs;
+use std::io;
+use std::os::unix::fs::{chown, FileTypeExt, PermissionsExt};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process;
+
+use tokio::net::{TcpListener, UnixListener};
+use tokio_vsock::VsockListener;
+use tracing::warn;
+
+/// A socket the agent accepts clients on
+pub enum Listener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+    Vsock(VsockListener),
+}
+
+/// Mode and ownership applied to the UNIX sockets the agent listens on
+#[derive(Debug, Clone, Default)]
+pub struct SocketPermissions {
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+}
+
+/// Bind a UNIX socket at `path`, replacing the socket left behind by an
+/// agent that is no longer running. Fails if another agent is listening on
+/// `path`.
+///
+/// The socket is bound under a temporary name and moved into place once its
+/// permissions are applied, so that it never shows up at `path` with looser
+/// ones, and a stale socket is replaced without leaving `path` free for
+/// somebody else to bind in the meantime.
+pub fn bind_unix(path: &Path, permissions: &SocketPermissions) -> io::Result<UnixListener> {
+    check_stale(path)?;
+
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a socket path", path.display()),
+        )
+    })?;
+    let temp = path.with_file_name(format!(".{}.{}", name.to_string_lossy(), process::id()));
+
+    let listener = UnixListener::bind(&temp)?;
+    let res = apply(&temp, permissions).and_then(|()| fs::rename(&temp, path));
+    if let Err(e) = res {
+        let _ = fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    Ok(listener)
+}
+
+fn apply(path: &Path, permissions: &SocketPermissions) -> io::Result<()> {
+    if let Some(mode) = permissions.mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    if permissions.uid.is_some() || permissions.gid.is_some() {
+        chown(path, permissions.uid, permissions.gid)?;
+    }
+
+    Ok(())
+}
+
+/// Fail unless `path` is free, or holds a socket no agent listens on
+fn check_stale(path: &Path) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    match UnixStream::connect(path) {
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("another agent is listening on {}", path.display()),
+        )),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            warn!("Replacing stale socket {}", path.display());
+            Ok(())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn stale_and_live_sockets() {
+        let dir = std::env::temp_dir().join(format!("vaccel-agent-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.sock");
+        let permissions = SocketPermissions {
+            mode: Some(0o660),
+            ..Default::default()
+        };
+
+        let listener = bind_unix(&path, &permissions).expect("Could not bind socket");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let err = bind_unix(&path, &permissions).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        // The socket file outlives the listener, as after a crash
+        drop(listener);
+        assert!(path.exists());
+        bind_unix(&path, &permissions).expect("Could not replace stale socket");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let file = dir.join("file");
+        fs::write(&file, "").unwrap();
+        let err = bind_unix(&file, &permissions).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+//! Integration with the systemd service manager: socket activation and
+//! readiness notification
+//!
+//! Both are no-ops when the agent is not started by systemd.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io;
+use std::mem;
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::net::{self, UnixDatagram};
+
+use tokio::net::{TcpListener, UnixListener};
+use tokio_vsock::VsockListener;
+
+use crate::socket::Listener;
+
+/// The first file descriptor passed by systemd
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Take the listening sockets passed by systemd through `LISTEN_FDS`. The
+/// variables are removed from the environment, so that the sockets are only
+/// taken once.
+pub fn listen_fds() -> io::Result<Vec<Listener>> {
+    let pid = env::var("LISTEN_PID").ok();
+    let fds = env::var("LISTEN_FDS").ok();
+    for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+        env::remove_var(name);
+    }
+
+    listen_fds_from(pid, fds)
+}
+
+/// Take the listening sockets described by the values of `LISTEN_PID` and
+/// `LISTEN_FDS`
+fn listen_fds_from(pid: Option<String>, fds: Option<String>) -> io::Result<Vec<Listener>> {
+    // The sockets are meant for another process
+    if pid.and_then(|pid| pid.parse().ok()) != Some(std::process::id()) {
+        return Ok(Vec::new());
+    }
+
+    let count: RawFd = match fds.and_then(|fds| fds.parse().ok()) {
+        Some(count) => count,
+        None => return Ok(Vec::new()),
+    };
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| unsafe { listener_from_fd(fd) })
+        .collect()
+}
+
+/// Wrap a listening socket, according to its address family
+///
+/// # Safety
+///
+/// `fd` must be an open socket that is not owned by anything else
+unsafe fn listener_from_fd(fd: RawFd) -> io::Result<Listener> {
+    if libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addr: libc::sockaddr_storage = mem::zeroed();
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    if libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    match addr.ss_family as libc::c_int {
+        libc::AF_UNIX => {
+            let listener = net::UnixListener::from_raw_fd(fd);
+            listener.set_nonblocking(true)?;
+            Ok(Listener::Unix(UnixListener::from_std(listener)?))
+        }
+        libc::AF_INET | libc::AF_INET6 => {
+            let listener = std::net::TcpListener::from_raw_fd(fd);
+            listener.set_nonblocking(true)?;
+            Ok(Listener::Tcp(TcpListener::from_std(listener)?))
+        }
+        libc::AF_VSOCK => Ok(Listener::Vsock(VsockListener::from_raw_fd(fd))),
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("socket {} has unsupported address family {}", fd, family),
+        )),
+    }
+}
+
+/// Send a state notification, e.g. `READY=1`, to the service manager.
+/// Returns whether the agent runs under a service manager expecting
+/// notifications.
+pub fn notify(state: &str) -> io::Result<bool> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(path) => notify_socket(&path, state).map(|()| true),
+        None => Ok(false),
+    }
+}
+
+/// Send a state notification to the service manager listening on `path`,
+/// the value of `NOTIFY_SOCKET`
+fn notify_socket(path: &OsStr, state: &str) -> io::Result<()> {
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_encoded_bytes();
+    let addr = match bytes.strip_prefix(b"@") {
+        Some(name) => net::SocketAddr::from_abstract_name(name)?,
+        None => net::SocketAddr::from_pathname(path)?,
+    };
+    socket.send_to_addr(state.as_bytes(), &addr)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::fs;
+    use std::os::unix::io::IntoRawFd;
+
+    #[test]
+    fn notifications() {
+        let dir = env::temp_dir().join(format!("vaccel-notify-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let manager = UnixDatagram::bind(&path).unwrap();
+
+        notify_socket(path.as_os_str(), "READY=1").unwrap();
+        notify_socket(path.as_os_str(), "STOPPING=1").unwrap();
+
+        let mut buf = [0; 64];
+        let len = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        let len = manager.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"STOPPING=1");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn passed_listeners() {
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        match unsafe { listener_from_fd(tcp.into_raw_fd()) } {
+            Ok(Listener::Tcp(_)) => {}
+            _ => panic!("Expected a TCP listener"),
+        }
+
+        let dir = env::temp_dir().join(format!("vaccel-listen-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let unix = net::UnixListener::bind(dir.join("agent.sock")).unwrap();
+        match unsafe { listener_from_fd(unix.into_raw_fd()) } {
+            Ok(Listener::Unix(_)) => {}
+            _ => panic!("Expected a UNIX listener"),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        // Not meant for this process, or not passed at all
+        let (other, count) = (Some("1".to_string()), Some("1".to_string()));
+        assert!(listen_fds_from(other, count.clone()).unwrap().is_empty());
+        assert!(listen_fds_from(None, count).unwrap().is_empty());
+        let pid = Some(std::process::id().to_string());
+        assert!(listen_fds_from(pid, None).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,22 @@
+extern crate cbindgen;
+
+use std::env;
+use std::path::PathBuf;
+
+/// Generate the C header in `OUT_DIR`, so that building never writes to the
+/// source tree. The copy under `include/` is kept up to date by a test.
+fn main() {
+    let crate_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    let config = cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")).unwrap();
+
+    cbindgen::Builder::new()
+        .with_crate(&crate_dir)
+        .with_config(config)
+        .generate()
+        .expect("Could not generate C header")
+        .write_to_file(out_dir.join("vaccel.h"));
+
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,446 @@
+#ifndef VACCEL_H
+#define VACCEL_H
+
+/* Generated by cbindgen from vaccel-capi. Do not edit by hand. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+#define VACCEL_OK 0
+
+#define VACCEL_EPERM 1
+
+#define VACCEL_ENOENT 2
+
+#define VACCEL_EIO 5
+
+#define VACCEL_ENOMEM 12
+
+#define VACCEL_EACCES 13
+
+#define VACCEL_EBUSY 16
+
+#define VACCEL_EINVAL 22
+
+#define VACCEL_ENOTSUP 95
+
+#define VACCEL_ETIMEDOUT 110
+
+#define VACCEL_ECANCELED 125
+
+#define VACCEL_ESESS 128
+
+#define VACCEL_EBACKEND 129
+
+/**
+ * Supported resource types
+ */
+enum vaccel_resource_t {
+  VACCEL_RES_TF_MODEL = 0,
+  VACCEL_RES_TF_SAVED_MODEL,
+  VACCEL_RES_MAX,
+};
+
+/**
+ * Element types of TensorFlow tensors, numbered as in `TF_DataType`
+ */
+enum vaccel_tf_data_type {
+  VACCEL_TF_FLOAT = 1,
+  VACCEL_TF_DOUBLE = 2,
+  VACCEL_TF_INT32 = 3,
+  VACCEL_TF_UINT8 = 4,
+  VACCEL_TF_INT16 = 5,
+  VACCEL_TF_INT8 = 6,
+  VACCEL_TF_STRING = 7,
+  VACCEL_TF_INT64 = 9,
+  VACCEL_TF_BOOL = 10,
+  VACCEL_TF_UINT16 = 17,
+};
+
+/**
+ * Resources associated with a session
+ */
+struct session_resources;
+
+/**
+ * A TensorFlow SavedModel, either in a directory or in memory
+ */
+struct vaccel_tf_saved_model;
+
+/**
+ * A vAccel session
+ */
+struct vaccel_session {
+  /**
+   * Session identifier
+   */
+  uint32_t session_id;
+  /**
+   * Resources registered with the session
+   */
+  struct session_resources *resources;
+  /**
+   * Flags the session was created with
+   */
+  uint32_t hint;
+  /**
+   * Private data of the implementation
+   */
+  void *priv;
+};
+
+typedef int64_t vaccel_id_t;
+
+/**
+ * A resource registered with the vAccel agent
+ */
+struct vaccel_resource {
+  /**
+   * Resource identifier, assigned when the resource is created
+   */
+  vaccel_id_t id;
+  /**
+   * Type of the resource
+   */
+  enum vaccel_resource_t type;
+  /**
+   * Type specific data of the resource
+   */
+  void *data;
+};
+
+/**
+ * Outcome of a TensorFlow operation
+ */
+struct vaccel_tf_status {
+  /**
+   * 0 on success
+   */
+  uint8_t error_code;
+  /**
+   * Description of the error, if any. Released by
+   * `vaccel_tf_status_destroy`
+   */
+  const char *message;
+};
+
+/**
+ * A dense TensorFlow tensor, with its elements in row-major order
+ */
+struct vaccel_tf_tensor {
+  /**
+   * Elements of the tensor
+   */
+  void *data;
+  /**
+   * Size of `data` in bytes
+   */
+  size_t size;
+  /**
+   * Dimensions of the tensor
+   */
+  int64_t *dims;
+  /**
+   * Number of dimensions
+   */
+  int nr_dims;
+  /**
+   * Element type
+   */
+  enum vaccel_tf_data_type data_type;
+};
+
+/**
+ * An input or output node of a TensorFlow graph
+ */
+struct vaccel_tf_node {
+  /**
+   * Name of the node, NUL terminated
+   */
+  const char *name;
+  /**
+   * Index of the output of the node
+   */
+  int id;
+};
+
+/**
+ * A buffer holding a serialized TensorFlow protobuf
+ */
+struct vaccel_tf_buffer {
+  /**
+   * Contents of the buffer
+   */
+  void *data;
+  /**
+   * Size of `data` in bytes
+   */
+  size_t size;
+};
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create a new session with the vAccel agent
+ *
+ * # Safety
+ *
+ * `sess` must be NULL or point to a writable `vaccel_session`
+ */
+int vaccel_sess_init(struct vaccel_session *sess, uint32_t flags);
+
+/**
+ * Destroy a session created with `vaccel_sess_init`. If that fails, the
+ * session is left untouched, so that freeing it can be retried.
+ *
+ * # Safety
+ *
+ * `sess` must be NULL or point to a session initialized with
+ * `vaccel_sess_init`
+ */
+int vaccel_sess_free(struct vaccel_session *sess);
+
+/**
+ * Associate a resource with a session
+ *
+ * # Safety
+ *
+ * `sess` must be NULL or point to a session initialized with
+ * `vaccel_sess_init` and `resource` must be NULL or point to a valid
+ * `vaccel_resource`
+ */
+int vaccel_sess_register(struct vaccel_session *sess, struct vaccel_resource *resource);
+
+/**
+ * Remove the association of a resource with a session
+ *
+ * # Safety
+ *
+ * Same as `vaccel_sess_register`
+ */
+int vaccel_sess_unregister(struct vaccel_session *sess, struct vaccel_resource *resource);
+
+/**
+ * Create a resource of type `type_` from `data` and register it with the
+ * agent. For `VACCEL_RES_TF_SAVED_MODEL`, `data` points to a
+ * `vaccel_tf_saved_model`.
+ *
+ * # Safety
+ *
+ * `res` must be NULL or point to a writable `vaccel_resource` and `data`
+ * must point to an object matching `type_`
+ */
+int vaccel_resource_new(struct vaccel_resource *res, enum vaccel_resource_t type_, void *data);
+
+/**
+ * Release a resource created with `vaccel_resource_new`, unregistering it
+ * from the agent. Models must be unloaded first.
+ *
+ * # Safety
+ *
+ * `res` must be NULL or point to a valid `vaccel_resource`
+ */
+int vaccel_resource_destroy(struct vaccel_resource *res);
+
+struct vaccel_tf_saved_model *vaccel_tf_saved_model_new(void);
+
+/**
+ * # Safety
+ *
+ * `model` must be NULL or have been returned by `vaccel_tf_saved_model_new`
+ */
+int vaccel_tf_saved_model_destroy(struct vaccel_tf_saved_model *model);
+
+/**
+ * Identifier of the model once registered, 0 before that
+ *
+ * # Safety
+ *
+ * `model` must be NULL or point to a valid `vaccel_tf_saved_model`
+ */
+vaccel_id_t vaccel_tf_saved_model_id(const struct vaccel_tf_saved_model *model);
+
+/**
+ * Set the export directory of the model
+ *
+ * # Safety
+ *
+ * `model` must be NULL or point to a valid `vaccel_tf_saved_model` and
+ * `path` must be NULL or a NUL terminated string
+ */
+int vaccel_tf_saved_model_set_path(struct vaccel_tf_saved_model *model, const char *path);
+
+/**
+ * Set the in-memory protobuf of the model
+ *
+ * # Safety
+ *
+ * `model` must be NULL or point to a valid `vaccel_tf_saved_model` and
+ * `ptr` must be NULL or valid for reads of `len` bytes
+ */
+int vaccel_tf_saved_model_set_model(struct vaccel_tf_saved_model *model,
+                                    const uint8_t *ptr,
+                                    size_t len);
+
+/**
+ * Set the in-memory checkpoint of the model
+ *
+ * # Safety
+ *
+ * Same as `vaccel_tf_saved_model_set_model`
+ */
+int vaccel_tf_saved_model_set_checkpoint(struct vaccel_tf_saved_model *model,
+                                         const uint8_t *ptr,
+                                         size_t len);
+
+/**
+ * Set the in-memory variable index of the model
+ *
+ * # Safety
+ *
+ * Same as `vaccel_tf_saved_model_set_model`
+ */
+int vaccel_tf_saved_model_set_var_index(struct vaccel_tf_saved_model *model,
+                                        const uint8_t *ptr,
+                                        size_t len);
+
+/**
+ * Release the message of a status filled by a TensorFlow operation
+ *
+ * # Safety
+ *
+ * `status` must be NULL or point to a status filled by this library
+ */
+int vaccel_tf_status_destroy(struct vaccel_tf_status *status);
+
+/**
+ * Load a registered SavedModel in a TensorFlow session
+ *
+ * # Safety
+ *
+ * `session` must be NULL or point to a session initialized with
+ * `vaccel_sess_init`, `model` must be NULL or point to a valid
+ * `vaccel_tf_saved_model` and `status` must be NULL or point to a writable
+ * `vaccel_tf_status`
+ */
+int vaccel_tf_session_load(struct vaccel_session *session,
+                           struct vaccel_tf_saved_model *model,
+                           struct vaccel_tf_status *status);
+
+/**
+ * Unload the TensorFlow session of a SavedModel
+ *
+ * # Safety
+ *
+ * Same as `vaccel_tf_session_load`
+ */
+int vaccel_tf_session_delete(struct vaccel_session *session,
+                             struct vaccel_tf_saved_model *model,
+                             struct vaccel_tf_status *status);
+
+/**
+ * Create a tensor with dimensions `dims` and no data, released with
+ * `vaccel_tf_tensor_destroy`
+ *
+ * # Safety
+ *
+ * `dims` must be NULL or valid for reads of `nr_dims` elements
+ */
+struct vaccel_tf_tensor *vaccel_tf_tensor_new(int nr_dims,
+                                              const int64_t *dims,
+                                              enum vaccel_tf_data_type type_);
+
+/**
+ * Create a tensor with dimensions `dims` and `total_size` bytes of zeroed
+ * data, released with `vaccel_tf_tensor_destroy`
+ *
+ * # Safety
+ *
+ * Same as `vaccel_tf_tensor_new`
+ */
+struct vaccel_tf_tensor *vaccel_tf_tensor_allocate(int nr_dims,
+                                                   const int64_t *dims,
+                                                   enum vaccel_tf_data_type type_,
+                                                   size_t total_size);
+
+/**
+ * Point the data of `tensor` to `size` bytes at `data`. The data remains
+ * owned by the caller, and must outlive its uses through `tensor`.
+ *
+ * # Safety
+ *
+ * `tensor` must be NULL or point to a valid `vaccel_tf_tensor`
+ */
+int vaccel_tf_tensor_set_data(struct vaccel_tf_tensor *tensor, void *data, size_t size);
+
+/**
+ * Data of `tensor`, NULL if it has none
+ *
+ * # Safety
+ *
+ * `tensor` must be NULL or point to a valid `vaccel_tf_tensor`
+ */
+void *vaccel_tf_tensor_get_data(const struct vaccel_tf_tensor *tensor);
+
+/**
+ * Release a tensor created by this library, along with the data it
+ * allocated. Data set with `vaccel_tf_tensor_set_data` is left alone.
+ *
+ * # Safety
+ *
+ * `tensor` must be NULL or have been returned by `vaccel_tf_tensor_new`,
+ * `vaccel_tf_tensor_allocate` or `vaccel_tf_session_run`
+ */
+int vaccel_tf_tensor_destroy(struct vaccel_tf_tensor *tensor);
+
+/**
+ * Create a node named `name`, released with `vaccel_tf_node_destroy`
+ *
+ * # Safety
+ *
+ * `name` must be NULL or a NUL terminated string
+ */
+struct vaccel_tf_node *vaccel_tf_node_new(const char *name, int id);
+
+/**
+ * Release a node created with `vaccel_tf_node_new`
+ *
+ * # Safety
+ *
+ * `node` must be NULL or have been returned by `vaccel_tf_node_new`
+ */
+int vaccel_tf_node_destroy(struct vaccel_tf_node *node);
+
+/**
+ * Run inference on a loaded SavedModel, feeding `in_` to `in_nodes` and
+ * fetching `out_nodes`. On success, `out` is filled with `nr_outputs`
+ * tensors, each released with `vaccel_tf_tensor_destroy`.
+ *
+ * Run options are not passed on to the agent: `run_options` must be NULL
+ * or empty, or the call fails with `VACCEL_ENOTSUP`.
+ *
+ * # Safety
+ *
+ * Same as `vaccel_tf_session_load`. In addition, `run_options` must be
+ * NULL or point to a valid `vaccel_tf_buffer`, `in_nodes` and `in_` must
+ * be valid for reads of `nr_inputs` elements, and `out_nodes` and `out`
+ * valid for reads and writes respectively of `nr_outputs` elements
+ */
+int vaccel_tf_session_run(struct vaccel_session *session,
+                          const struct vaccel_tf_saved_model *model,
+                          const struct vaccel_tf_buffer *run_options,
+                          const struct vaccel_tf_node *in_nodes,
+                          struct vaccel_tf_tensor *const *in_,
+                          int nr_inputs,
+                          const struct vaccel_tf_node *out_nodes,
+                          struct vaccel_tf_tensor **out,
+                          int nr_outputs,
+                          struct vaccel_tf_status *status);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VACCEL_H */
@@ -0,0 +1,1132 @@
+//! C bindings of the vAccel runtime API
+//!
+//! This crate builds `libvaccel.so`, which implements the entry points of
+//! the vAccel C API on top of `vaccel::client`, so that existing C/C++
+//! applications can talk to a vAccel agent unchanged. The agent is located
+//! through the `VACCEL_RPC_ADDRESS` environment variable, which holds an
+//! endpoint URI (`vsock://2:2048` by default), and `VACCEL_RPC_TOKEN`, if
+//! the agent requires authentication.
+//!
+//! The supported subset of the v0.5 vAccel API is sessions, resources and
+//! TensorFlow SavedModels: `struct vaccel_session` and the TensorFlow
+//! structures have the layout of their upstream counterparts, and the
+//! TensorFlow functions the same signatures. `struct vaccel_resource` only
+//! carries the id, type and data of a resource, and
+//! `struct vaccel_tf_saved_model` is opaque: models are set up through its
+//! functions and registered with `vaccel_resource_new`.
+
+#![allow(non_camel_case_types)]
+
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::path::PathBuf;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use log::error;
+
+use vaccel::blocking::Vaccel;
+use vaccel::client::VaccelConfig;
+use vaccel::resource::Resource;
+use vaccel::session::Session;
+use vaccel::tensorflow::models::TensorflowSavedModelBuilder;
+use vaccel::tensorflow::{DataType, Node, Tensor};
+use vaccel::Error;
+
+pub const VACCEL_OK: c_int = 0;
+pub const VACCEL_EPERM: c_int = 1;
+pub const VACCEL_ENOENT: c_int = 2;
+pub const VACCEL_EIO: c_int = 5;
+pub const VACCEL_ENOMEM: c_int = 12;
+pub const VACCEL_EACCES: c_int = 13;
+pub const VACCEL_EBUSY: c_int = 16;
+pub const VACCEL_EINVAL: c_int = 22;
+pub const VACCEL_ENOTSUP: c_int = 95;
+pub const VACCEL_ETIMEDOUT: c_int = 110;
+pub const VACCEL_ECANCELED: c_int = 125;
+pub const VACCEL_ESESS: c_int = 128;
+pub const VACCEL_EBACKEND: c_int = 129;
+
+/// Environment variable with the URI of the vAccel agent
+const RPC_ADDRESS: &str = "VACCEL_RPC_ADDRESS";
+/// Environment variable with the token to authenticate with
+const RPC_TOKEN: &str = "VACCEL_RPC_TOKEN";
+const DEFAULT_RPC_ADDRESS: &str = "vsock://2:2048";
+
+static CLIENT: Mutex<Option<Vaccel>> = Mutex::new(None);
+
+/// The client shared by all API calls of the process, connected on first use
+fn client() -> Result<Vaccel, Error> {
+    let mut client = CLIENT.lock().unwrap();
+    if let Some(ref client) = *client {
+        return Ok(client.clone());
+    }
+
+    let uri = env::var(RPC_ADDRESS).unwrap_or_else(|_| DEFAULT_RPC_ADDRESS.to_string());
+    let mut config = VaccelConfig::new(uri.parse()?);
+    if let Ok(token) = env::var(RPC_TOKEN) {
+        config = config.token(token);
+    }
+
+    let vaccel = Vaccel::new(config)?;
+    *client = Some(vaccel.clone());
+    Ok(vaccel)
+}
+
+fn error_code(err: &Error) -> c_int {
+    match err {
+        Error::InvalidArgument => VACCEL_EINVAL,
+        Error::Plugin(_) => VACCEL_EBACKEND,
+        Error::SessionLost(_) | Error::ResourceLost(_) => VACCEL_ESESS,
+        Error::DeadlineExceeded => VACCEL_ETIMEDOUT,
+        Error::Cancelled => VACCEL_ECANCELED,
+        Error::Busy => VACCEL_EBUSY,
+        Error::QuotaExceeded(_) => VACCEL_ENOMEM,
+        Error::Unauthenticated => VACCEL_EACCES,
+        Error::PermissionDenied => VACCEL_EPERM,
+        Error::Incompatible { .. } | Error::Unsupported(_) => VACCEL_ENOTSUP,
+        _ => VACCEL_EIO,
+    }
+}
+
+/// Log `err` and turn it into a vAccel error code
+fn fail(func: &str, err: Error) -> c_int {
+    error!("{}: {}", func, err);
+    error_code(&err)
+}
+
+pub type vaccel_id_t = i64;
+
+/// Resources associated with a session
+pub struct session_resources {
+    ids: Vec<vaccel_id_t>,
+}
+
+/// cbindgen:field-names=[session_id, resources, hint, priv]
+/// A vAccel session
+#[repr(C)]
+pub struct vaccel_session {
+    /// Session identifier
+    pub session_id: u32,
+    /// Resources registered with the session
+    pub resources: *mut session_resources,
+    /// Flags the session was created with
+    pub hint: u32,
+    /// Private data of the implementation
+    pub priv_: *mut c_void,
+}
+
+/// # Safety
+///
+/// `sess` must be NULL or point to a valid `vaccel_session`
+unsafe fn session<'a>(sess: *mut vaccel_session) -> Option<&'a Session> {
+    sess.as_ref()
+        .and_then(|sess| (sess.priv_ as *const Session).as_ref())
+}
+
+/// # Safety
+///
+/// `sess` must be NULL or point to a valid `vaccel_session`
+unsafe fn session_resources<'a>(sess: *mut vaccel_session) -> Option<&'a mut session_resources> {
+    sess.as_mut().and_then(|sess| sess.resources.as_mut())
+}
+
+/// Create a new session with the vAccel agent
+///
+/// # Safety
+///
+/// `sess` must be NULL or point to a writable `vaccel_session`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_sess_init(sess: *mut vaccel_session, flags: u32) -> c_int {
+    let sess = match sess.as_mut() {
+        None => return VACCEL_EINVAL,
+        Some(sess) => sess,
+    };
+
+    let session = match client().and_then(|client| client.new_session()) {
+        Ok(session) => session,
+        Err(e) => return fail("vaccel_sess_init", e),
+    };
+
+    sess.session_id = session.id() as u32;
+    sess.resources = Box::into_raw(Box::new(session_resources { ids: Vec::new() }));
+    sess.hint = flags;
+    sess.priv_ = Box::into_raw(Box::new(session)) as *mut c_void;
+
+    VACCEL_OK
+}
+
+/// Destroy a session created with `vaccel_sess_init`. If that fails, the
+/// session is left untouched, so that freeing it can be retried.
+///
+/// # Safety
+///
+/// `sess` must be NULL or point to a session initialized with
+/// `vaccel_sess_init`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_sess_free(sess: *mut vaccel_session) -> c_int {
+    let session = match session(sess) {
+        None => return VACCEL_EINVAL,
+        Some(session) => session,
+    };
+
+    if let Err(e) = client().and_then(|client| client.destroy_session(session)) {
+        return fail("vaccel_sess_free", e);
+    }
+
+    let sess = &mut *sess;
+    drop(Box::from_raw(sess.priv_ as *mut Session));
+    sess.priv_ = ptr::null_mut();
+    if !sess.resources.is_null() {
+        drop(Box::from_raw(sess.resources));
+        sess.resources = ptr::null_mut();
+    }
+
+    VACCEL_OK
+}
+
+/// Supported resource types
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum vaccel_resource_t {
+    VACCEL_RES_TF_MODEL = 0,
+    VACCEL_RES_TF_SAVED_MODEL,
+    VACCEL_RES_MAX,
+}
+
+/// cbindgen:field-names=[id, type, data]
+/// A resource registered with the vAccel agent
+#[repr(C)]
+pub struct vaccel_resource {
+    /// Resource identifier, assigned when the resource is created
+    pub id: vaccel_id_t,
+    /// Type of the resource
+    pub type_: vaccel_resource_t,
+    /// Type specific data of the resource
+    pub data: *mut c_void,
+}
+
+/// Associate a resource with a session
+///
+/// # Safety
+///
+/// `sess` must be NULL or point to a session initialized with
+/// `vaccel_sess_init` and `resource` must be NULL or point to a valid
+/// `vaccel_resource`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_sess_register(
+    sess: *mut vaccel_session,
+    resource: *mut vaccel_resource,
+) -> c_int {
+    match (session_resources(sess), resource.as_ref()) {
+        (Some(resources), Some(resource)) if resource.id > 0 => {
+            if !resources.ids.contains(&resource.id) {
+                resources.ids.push(resource.id);
+            }
+            VACCEL_OK
+        }
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// Remove the association of a resource with a session
+///
+/// # Safety
+///
+/// Same as `vaccel_sess_register`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_sess_unregister(
+    sess: *mut vaccel_session,
+    resource: *mut vaccel_resource,
+) -> c_int {
+    match (session_resources(sess), resource.as_ref()) {
+        (Some(resources), Some(resource)) => {
+            match resources.ids.iter().position(|&id| id == resource.id) {
+                Some(index) => {
+                    resources.ids.remove(index);
+                    VACCEL_OK
+                }
+                None => VACCEL_ENOENT,
+            }
+        }
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// Create a resource of type `type_` from `data` and register it with the
+/// agent. For `VACCEL_RES_TF_SAVED_MODEL`, `data` points to a
+/// `vaccel_tf_saved_model`.
+///
+/// # Safety
+///
+/// `res` must be NULL or point to a writable `vaccel_resource` and `data`
+/// must point to an object matching `type_`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_resource_new(
+    res: *mut vaccel_resource,
+    type_: vaccel_resource_t,
+    data: *mut c_void,
+) -> c_int {
+    let res = match res.as_mut() {
+        None => return VACCEL_EINVAL,
+        Some(res) => res,
+    };
+
+    let model = match type_ {
+        vaccel_resource_t::VACCEL_RES_TF_SAVED_MODEL => {
+            match (data as *mut vaccel_tf_saved_model).as_mut() {
+                None => return VACCEL_EINVAL,
+                Some(model) => model,
+            }
+        }
+        vaccel_resource_t::VACCEL_RES_TF_MODEL => return VACCEL_ENOTSUP,
+        vaccel_resource_t::VACCEL_RES_MAX => return VACCEL_EINVAL,
+    };
+
+    let mut builder = TensorflowSavedModelBuilder::new();
+    if let Some(ref path) = model.path {
+        builder = builder.export_dir(path.clone());
+    }
+    if let Some(ref bytes) = model.model {
+        builder = builder.model(bytes.clone());
+    }
+    if let Some(ref bytes) = model.checkpoint {
+        builder = builder.checkpoint(bytes.clone());
+    }
+    if let Some(ref bytes) = model.var_index {
+        builder = builder.var_index(bytes.clone());
+    }
+
+    let id = match builder
+        .build()
+        .and_then(|model| client()?.register_resource(Resource::TensorflowSavedModel(model)))
+    {
+        Ok(id) => id as vaccel_id_t,
+        Err(e) => return fail("vaccel_resource_new", e),
+    };
+
+    model.id = id;
+    res.id = id;
+    res.type_ = type_;
+    res.data = data;
+
+    VACCEL_OK
+}
+
+/// Release a resource created with `vaccel_resource_new`, unregistering it
+/// from the agent. Models must be unloaded first.
+///
+/// # Safety
+///
+/// `res` must be NULL or point to a valid `vaccel_resource`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_resource_destroy(res: *mut vaccel_resource) -> c_int {
+    let res = match res.as_mut() {
+        Some(res) if res.id > 0 => res,
+        _ => return VACCEL_EINVAL,
+    };
+
+    if let Err(e) = client().and_then(|client| client.unregister_resource(res.id as u64)) {
+        return fail("vaccel_resource_destroy", e);
+    }
+
+    if res.type_ == vaccel_resource_t::VACCEL_RES_TF_SAVED_MODEL {
+        if let Some(model) = (res.data as *mut vaccel_tf_saved_model).as_mut() {
+            model.id = 0;
+        }
+    }
+    res.id = 0;
+    res.data = ptr::null_mut();
+
+    VACCEL_OK
+}
+
+/// A TensorFlow SavedModel, either in a directory or in memory
+#[derive(Default)]
+pub struct vaccel_tf_saved_model {
+    id: vaccel_id_t,
+    path: Option<PathBuf>,
+    model: Option<Vec<u8>>,
+    checkpoint: Option<Vec<u8>>,
+    var_index: Option<Vec<u8>>,
+}
+
+#[no_mangle]
+pub extern "C" fn vaccel_tf_saved_model_new() -> *mut vaccel_tf_saved_model {
+    Box::into_raw(Box::new(vaccel_tf_saved_model::default()))
+}
+
+/// # Safety
+///
+/// `model` must be NULL or have been returned by `vaccel_tf_saved_model_new`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_destroy(model: *mut vaccel_tf_saved_model) -> c_int {
+    if model.is_null() {
+        return VACCEL_EINVAL;
+    }
+
+    drop(Box::from_raw(model));
+    VACCEL_OK
+}
+
+/// Identifier of the model once registered, 0 before that
+///
+/// # Safety
+///
+/// `model` must be NULL or point to a valid `vaccel_tf_saved_model`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_id(
+    model: *const vaccel_tf_saved_model,
+) -> vaccel_id_t {
+    model.as_ref().map_or(0, |model| model.id)
+}
+
+/// Set the export directory of the model
+///
+/// # Safety
+///
+/// `model` must be NULL or point to a valid `vaccel_tf_saved_model` and
+/// `path` must be NULL or a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_set_path(
+    model: *mut vaccel_tf_saved_model,
+    path: *const c_char,
+) -> c_int {
+    match (model.as_mut(), path.is_null()) {
+        (Some(model), false) => match CStr::from_ptr(path).to_str() {
+            Ok(path) => {
+                model.path = Some(PathBuf::from(path));
+                VACCEL_OK
+            }
+            Err(_) => VACCEL_EINVAL,
+        },
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// # Safety
+///
+/// `ptr` must be NULL or valid for reads of `len` bytes
+unsafe fn copy_bytes(ptr: *const u8, len: usize) -> Option<Vec<u8>> {
+    if ptr.is_null() {
+        return None;
+    }
+
+    Some(slice::from_raw_parts(ptr, len).to_vec())
+}
+
+/// Set the in-memory protobuf of the model
+///
+/// # Safety
+///
+/// `model` must be NULL or point to a valid `vaccel_tf_saved_model` and
+/// `ptr` must be NULL or valid for reads of `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_set_model(
+    model: *mut vaccel_tf_saved_model,
+    ptr: *const u8,
+    len: usize,
+) -> c_int {
+    match (model.as_mut(), copy_bytes(ptr, len)) {
+        (Some(model), Some(bytes)) => {
+            model.model = Some(bytes);
+            VACCEL_OK
+        }
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// Set the in-memory checkpoint of the model
+///
+/// # Safety
+///
+/// Same as `vaccel_tf_saved_model_set_model`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_set_checkpoint(
+    model: *mut vaccel_tf_saved_model,
+    ptr: *const u8,
+    len: usize,
+) -> c_int {
+    match (model.as_mut(), copy_bytes(ptr, len)) {
+        (Some(model), Some(bytes)) => {
+            model.checkpoint = Some(bytes);
+            VACCEL_OK
+        }
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// Set the in-memory variable index of the model
+///
+/// # Safety
+///
+/// Same as `vaccel_tf_saved_model_set_model`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_saved_model_set_var_index(
+    model: *mut vaccel_tf_saved_model,
+    ptr: *const u8,
+    len: usize,
+) -> c_int {
+    match (model.as_mut(), copy_bytes(ptr, len)) {
+        (Some(model), Some(bytes)) => {
+            model.var_index = Some(bytes);
+            VACCEL_OK
+        }
+        _ => VACCEL_EINVAL,
+    }
+}
+
+/// Outcome of a TensorFlow operation
+#[repr(C)]
+pub struct vaccel_tf_status {
+    /// 0 on success
+    pub error_code: u8,
+    /// Description of the error, if any. Released by
+    /// `vaccel_tf_status_destroy`
+    pub message: *const c_char,
+}
+
+/// # Safety
+///
+/// `status` must be NULL or point to a writable `vaccel_tf_status`
+unsafe fn set_status(status: *mut vaccel_tf_status, res: &vaccel::Result<()>) {
+    let status = match status.as_mut() {
+        None => return,
+        Some(status) => status,
+    };
+
+    match res {
+        Ok(()) => {
+            status.error_code = 0;
+            status.message = ptr::null();
+        }
+        Err(e) => {
+            status.error_code = error_code(e) as u8;
+            status.message = CString::new(e.to_string())
+                .map_or(ptr::null(), |msg| msg.into_raw() as *const c_char);
+        }
+    }
+}
+
+/// Release the message of a status filled by a TensorFlow operation
+///
+/// # Safety
+///
+/// `status` must be NULL or point to a status filled by this library
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_status_destroy(status: *mut vaccel_tf_status) -> c_int {
+    let status = match status.as_mut() {
+        None => return VACCEL_EINVAL,
+        Some(status) => status,
+    };
+
+    if !status.message.is_null() {
+        drop(CString::from_raw(status.message as *mut c_char));
+        status.message = ptr::null();
+    }
+
+    VACCEL_OK
+}
+
+/// # Safety
+///
+/// Same as `vaccel_tf_session_load`
+unsafe fn tf_session_op<F>(
+    func: &str,
+    session: *mut vaccel_session,
+    model: *const vaccel_tf_saved_model,
+    status: *mut vaccel_tf_status,
+    op: F,
+) -> c_int
+where
+    F: FnOnce(&Vaccel, u64) -> vaccel::Result<()>,
+{
+    let model = match (self::session(session), model.as_ref()) {
+        (Some(_), Some(model)) if model.id > 0 => model,
+        _ => return VACCEL_EINVAL,
+    };
+
+    let res = client().and_then(|client| op(&client, model.id as u64));
+    set_status(status, &res);
+
+    match res {
+        Ok(()) => VACCEL_OK,
+        Err(e) => fail(func, e),
+    }
+}
+
+/// Load a registered SavedModel in a TensorFlow session
+///
+/// # Safety
+///
+/// `session` must be NULL or point to a session initialized with
+/// `vaccel_sess_init`, `model` must be NULL or point to a valid
+/// `vaccel_tf_saved_model` and `status` must be NULL or point to a writable
+/// `vaccel_tf_status`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_session_load(
+    session: *mut vaccel_session,
+    model: *mut vaccel_tf_saved_model,
+    status: *mut vaccel_tf_status,
+) -> c_int {
+    tf_session_op(
+        "vaccel_tf_session_load",
+        session,
+        model,
+        status,
+        |client, id| client.tf_session_load(id),
+    )
+}
+
+/// Unload the TensorFlow session of a SavedModel
+///
+/// # Safety
+///
+/// Same as `vaccel_tf_session_load`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_session_delete(
+    session: *mut vaccel_session,
+    model: *mut vaccel_tf_saved_model,
+    status: *mut vaccel_tf_status,
+) -> c_int {
+    tf_session_op(
+        "vaccel_tf_session_delete",
+        session,
+        model,
+        status,
+        |client, id| client.tf_session_unload(id),
+    )
+}
+
+/// Element types of TensorFlow tensors, numbered as in `TF_DataType`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum vaccel_tf_data_type {
+    VACCEL_TF_FLOAT = 1,
+    VACCEL_TF_DOUBLE = 2,
+    VACCEL_TF_INT32 = 3,
+    VACCEL_TF_UINT8 = 4,
+    VACCEL_TF_INT16 = 5,
+    VACCEL_TF_INT8 = 6,
+    VACCEL_TF_STRING = 7,
+    VACCEL_TF_INT64 = 9,
+    VACCEL_TF_BOOL = 10,
+    VACCEL_TF_UINT16 = 17,
+}
+
+impl From<vaccel_tf_data_type> for DataType {
+    fn from(data_type: vaccel_tf_data_type) -> Self {
+        match data_type {
+            vaccel_tf_data_type::VACCEL_TF_FLOAT => DataType::Float,
+            vaccel_tf_data_type::VACCEL_TF_DOUBLE => DataType::Double,
+            vaccel_tf_data_type::VACCEL_TF_INT32 => DataType::Int32,
+            vaccel_tf_data_type::VACCEL_TF_UINT8 => DataType::UInt8,
+            vaccel_tf_data_type::VACCEL_TF_INT16 => DataType::Int16,
+            vaccel_tf_data_type::VACCEL_TF_INT8 => DataType::Int8,
+            vaccel_tf_data_type::VACCEL_TF_STRING => DataType::String,
+            vaccel_tf_data_type::VACCEL_TF_INT64 => DataType::Int64,
+            vaccel_tf_data_type::VACCEL_TF_BOOL => DataType::Bool,
+            vaccel_tf_data_type::VACCEL_TF_UINT16 => DataType::UInt16,
+        }
+    }
+}
+
+impl From<DataType> for vaccel_tf_data_type {
+    fn from(data_type: DataType) -> Self {
+        match data_type {
+            DataType::Float => vaccel_tf_data_type::VACCEL_TF_FLOAT,
+            DataType::Double => vaccel_tf_data_type::VACCEL_TF_DOUBLE,
+            DataType::Int32 => vaccel_tf_data_type::VACCEL_TF_INT32,
+            DataType::UInt8 => vaccel_tf_data_type::VACCEL_TF_UINT8,
+            DataType::Int16 => vaccel_tf_data_type::VACCEL_TF_INT16,
+            DataType::Int8 => vaccel_tf_data_type::VACCEL_TF_INT8,
+            DataType::String => vaccel_tf_data_type::VACCEL_TF_STRING,
+            DataType::Int64 => vaccel_tf_data_type::VACCEL_TF_INT64,
+            DataType::Bool => vaccel_tf_data_type::VACCEL_TF_BOOL,
+            DataType::UInt16 => vaccel_tf_data_type::VACCEL_TF_UINT16,
+        }
+    }
+}
+
+/// An input or output node of a TensorFlow graph
+#[repr(C)]
+pub struct vaccel_tf_node {
+    /// Name of the node, NUL terminated
+    pub name: *const c_char,
+    /// Index of the output of the node
+    pub id: c_int,
+}
+
+/// A dense TensorFlow tensor, with its elements in row-major order
+#[repr(C)]
+pub struct vaccel_tf_tensor {
+    /// Elements of the tensor
+    pub data: *mut c_void,
+    /// Size of `data` in bytes
+    pub size: usize,
+    /// Dimensions of the tensor
+    pub dims: *mut i64,
+    /// Number of dimensions
+    pub nr_dims: c_int,
+    /// Element type
+    pub data_type: vaccel_tf_data_type,
+}
+
+/// # Safety
+///
+/// `node` must point to a valid `vaccel_tf_node`
+unsafe fn node(node: &vaccel_tf_node) -> Option<Node> {
+    if node.name.is_null() {
+        return None;
+    }
+
+    Some(Node {
+        name: CStr::from_ptr(node.name).to_str().ok()?.to_string(),
+        id: node.id,
+    })
+}
+
+/// # Safety
+///
+/// `dims` must be NULL or valid for reads of `nr_dims` elements
+unsafe fn copy_dims(nr_dims: c_int, dims: *const i64) -> Option<Vec<i64>> {
+    match usize::try_from(nr_dims).ok()? {
+        0 => Some(Vec::new()),
+        _ if dims.is_null() => None,
+        nr_dims => Some(slice::from_raw_parts(dims, nr_dims).to_vec()),
+    }
+}
+
+/// # Safety
+///
+/// `tensor` must point to a valid `vaccel_tf_tensor`
+unsafe fn tensor(tensor: &vaccel_tf_tensor) -> Option<Tensor> {
+    let dims = copy_dims(tensor.nr_dims, tensor.dims)?;
+    let data = match tensor.size {
+        0 => Vec::new(),
+        size => copy_bytes(tensor.data as *const u8, size)?,
+    };
+
+    Some(Tensor {
+        dims,
+        data_type: tensor.data_type.into(),
+        data,
+    })
+}
+
+/// A tensor allocated by this library, along with the buffers it owns. The
+/// caller only sees `tensor`, so it must come first.
+#[repr(C)]
+struct OwnedTensor {
+    tensor: vaccel_tf_tensor,
+    dims: Box<[i64]>,
+    data: Option<Box<[u8]>>,
+}
+
+/// Hand a tensor over to the caller, who releases it with
+/// `vaccel_tf_tensor_destroy`
+fn into_raw_tensor(
+    dims: Vec<i64>,
+    data_type: vaccel_tf_data_type,
+    data: Option<Vec<u8>>,
+) -> *mut vaccel_tf_tensor {
+    let mut dims = dims.into_boxed_slice();
+    let mut data = data.map(Vec::into_boxed_slice);
+    let tensor = vaccel_tf_tensor {
+        data: data
+            .as_mut()
+            .map_or(ptr::null_mut(), |data| data.as_mut_ptr() as *mut c_void),
+        size: data.as_ref().map_or(0, |data| data.len()),
+        dims: dims.as_mut_ptr(),
+        nr_dims: dims.len() as c_int,
+        data_type,
+    };
+
+    Box::into_raw(Box::new(OwnedTensor { tensor, dims, data })) as *mut vaccel_tf_tensor
+}
+
+/// Create a tensor with dimensions `dims` and no data, released with
+/// `vaccel_tf_tensor_destroy`
+///
+/// # Safety
+///
+/// `dims` must be NULL or valid for reads of `nr_dims` elements
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_tensor_new(
+    nr_dims: c_int,
+    dims: *const i64,
+    type_: vaccel_tf_data_type,
+) -> *mut vaccel_tf_tensor {
+    match copy_dims(nr_dims, dims) {
+        None => ptr::null_mut(),
+        Some(dims) => into_raw_tensor(dims, type_, None),
+    }
+}
+
+/// Create a tensor with dimensions `dims` and `total_size` bytes of zeroed
+/// data, released with `vaccel_tf_tensor_destroy`
+///
+/// # Safety
+///
+/// Same as `vaccel_tf_tensor_new`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_tensor_allocate(
+    nr_dims: c_int,
+    dims: *const i64,
+    type_: vaccel_tf_data_type,
+    total_size: usize,
+) -> *mut vaccel_tf_tensor {
+    match copy_dims(nr_dims, dims) {
+        None => ptr::null_mut(),
+        Some(dims) => into_raw_tensor(dims, type_, Some(vec![0; total_size])),
+    }
+}
+
+/// Point the data of `tensor` to `size` bytes at `data`. The data remains
+/// owned by the caller, and must outlive its uses through `tensor`.
+///
+/// # Safety
+///
+/// `tensor` must be NULL or point to a valid `vaccel_tf_tensor`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_tensor_set_data(
+    tensor: *mut vaccel_tf_tensor,
+    data: *mut c_void,
+    size: usize,
+) -> c_int {
+    match tensor.as_mut() {
+        None => VACCEL_EINVAL,
+        Some(tensor) => {
+            tensor.data = data;
+            tensor.size = size;
+            VACCEL_OK
+        }
+    }
+}
+
+/// Data of `tensor`, NULL if it has none
+///
+/// # Safety
+///
+/// `tensor` must be NULL or point to a valid `vaccel_tf_tensor`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_tensor_get_data(tensor: *const vaccel_tf_tensor) -> *mut c_void {
+    tensor
+        .as_ref()
+        .map_or(ptr::null_mut(), |tensor| tensor.data)
+}
+
+/// Release a tensor created by this library, along with the data it
+/// allocated. Data set with `vaccel_tf_tensor_set_data` is left alone.
+///
+/// # Safety
+///
+/// `tensor` must be NULL or have been returned by `vaccel_tf_tensor_new`,
+/// `vaccel_tf_tensor_allocate` or `vaccel_tf_session_run`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_tensor_destroy(tensor: *mut vaccel_tf_tensor) -> c_int {
+    if tensor.is_null() {
+        return VACCEL_EINVAL;
+    }
+
+    drop(Box::from_raw(tensor as *mut OwnedTensor));
+    VACCEL_OK
+}
+
+/// A node allocated by this library, along with its name. The caller only
+/// sees `node`, so it must come first.
+#[repr(C)]
+struct OwnedNode {
+    node: vaccel_tf_node,
+    name: CString,
+}
+
+/// Create a node named `name`, released with `vaccel_tf_node_destroy`
+///
+/// # Safety
+///
+/// `name` must be NULL or a NUL terminated string
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_node_new(name: *const c_char, id: c_int) -> *mut vaccel_tf_node {
+    if name.is_null() {
+        return ptr::null_mut();
+    }
+
+    let name = CStr::from_ptr(name).to_owned();
+    let node = vaccel_tf_node {
+        name: name.as_ptr(),
+        id,
+    };
+    Box::into_raw(Box::new(OwnedNode { node, name })) as *mut vaccel_tf_node
+}
+
+/// Release a node created with `vaccel_tf_node_new`
+///
+/// # Safety
+///
+/// `node` must be NULL or have been returned by `vaccel_tf_node_new`
+#[no_mangle]
+pub unsafe extern "C" fn vaccel_tf_node_destroy(node: *mut vaccel_tf_node) -> c_int {
+    if node.is_null() {
+        return VACCEL_EINVAL;
+    }
+
+    drop(Box::from_raw(node as *mut OwnedNode));
+    VACCEL_OK
+}
+
+/// A buffer holding a serialized TensorFlow protobuf
+#[repr(C)]
+pub struct vaccel_tf_buffer {
+    /// Contents of the buffer
+    pub data: *mut c_void,
+    /// Size of `data` in bytes
+    pub size: usize,
+}
+
+/// Run inference on a loaded SavedModel, feeding `in_` to `in_nodes` and
+/// fetching `out_nodes`. On success, `out` is filled with `nr_outputs`
+/// tensors, each released with `vaccel_tf_tensor_destroy`.
+///
+/// Run options are not passed on to the agent: `run_options` must be NULL
+/// or empty, or the call fails with `VACCEL_ENOTSUP`.
+///
+/// # Safety
+///
+/// Same as `vaccel_tf_session_load`. In addition, `run_options` must be
+/// NULL or point to a valid `vaccel_tf_buffer`, `in_nodes` and `in_` must
+/// be valid for reads of `nr_inputs` elements, and `out_nodes` and `out`
+/// valid for reads and writes respectively of `nr_outputs` elements
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn vaccel_tf_session_run(
+    session: *mut vaccel_session,
+    model: *const vaccel_tf_saved_model,
+    run_options: *const vaccel_tf_buffer,
+    in_nodes: *const vaccel_tf_node,
+    in_: *const *mut vaccel_tf_tensor,
+    nr_inputs: c_int,
+    out_nodes: *const vaccel_tf_node,
+    out: *mut *mut vaccel_tf_tensor,
+    nr_outputs: c_int,
+    status: *mut vaccel_tf_status,
+) -> c_int {
+    let (nr_inputs, nr_outputs) = match (usize::try_from(nr_inputs), usize::try_from(nr_outputs)) {
+        (Ok(nr_inputs), Ok(nr_outputs)) => (nr_inputs, nr_outputs),
+        _ => return VACCEL_EINVAL,
+    };
+    if run_options.as_ref().is_some_and(|options| options.size > 0) {
+        return fail(
+            "vaccel_tf_session_run",
+            Error::Unsupported("run options".to_string()),
+        );
+    }
+    if (nr_inputs > 0 && (in_nodes.is_null() || in_.is_null()))
+        || (nr_outputs > 0 && (out_nodes.is_null() || out.is_null()))
+    {
+        return VACCEL_EINVAL;
+    }
+
+    let mut inputs = Vec::with_capacity(nr_inputs);
+    for i in 0..nr_inputs {
+        let input = match (*in_.add(i)).as_ref() {
+            None => return VACCEL_EINVAL,
+            Some(input) => input,
+        };
+        match (node(&*in_nodes.add(i)), tensor(input)) {
+            (Some(node), Some(tensor)) => inputs.push((node, tensor)),
+            _ => return VACCEL_EINVAL,
+        }
+    }
+    let outputs = match (0..nr_outputs)
+        .map(|i| node(&*out_nodes.add(i)))
+        .collect::<Option<Vec<_>>>()
+    {
+        None => return VACCEL_EINVAL,
+        Some(outputs) => outputs,
+    };
+
+    tf_session_op(
+        "vaccel_tf_session_run",
+        session,
+        model,
+        status,
+        |client, id| {
+            let tensors = client.tf_session_run(id, inputs, outputs)?;
+            if tensors.len() != nr_outputs {
+                return Err(Error::Plugin(format!(
+                    "expected {} output tensors, got {}",
+                    nr_outputs,
+                    tensors.len()
+                )));
+            }
+
+            for (i, tensor) in tensors.into_iter().enumerate() {
+                *out.add(i) =
+                    into_raw_tensor(tensor.dims, tensor.data_type.into(), Some(tensor.data));
+            }
+            Ok(())
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::mem::MaybeUninit;
+
+    #[test]
+    fn c_api_session_and_model() {
+        env::set_var(RPC_ADDRESS, "local");
+
+        unsafe {
+            let mut sess = MaybeUninit::<vaccel_session>::zeroed().assume_init();
+            assert_eq!(vaccel_sess_init(&mut sess, 0), VACCEL_OK);
+            assert_eq!(sess.session_id, 1);
+
+            let model = vaccel_tf_saved_model_new();
+            let path = CString::new("/tmp/model").unwrap();
+            assert_eq!(
+                vaccel_tf_saved_model_set_path(model, path.as_ptr()),
+                VACCEL_OK
+            );
+
+            let mut res = MaybeUninit::<vaccel_resource>::zeroed().assume_init();
+            assert_eq!(
+                vaccel_resource_new(
+                    &mut res,
+                    vaccel_resource_t::VACCEL_RES_TF_SAVED_MODEL,
+                    model as *mut c_void
+                ),
+                VACCEL_OK
+            );
+            assert_eq!(vaccel_tf_saved_model_id(model), res.id);
+            assert_eq!(vaccel_sess_register(&mut sess, &mut res), VACCEL_OK);
+
+            let mut status = MaybeUninit::<vaccel_tf_status>::zeroed().assume_init();
+            assert_eq!(
+                vaccel_tf_session_load(&mut sess, model, &mut status),
+                VACCEL_OK
+            );
+            assert_eq!(status.error_code, 0);
+
+            let name = CString::new("input").unwrap();
+            let in_node = vaccel_tf_node {
+                name: name.as_ptr(),
+                id: 0,
+            };
+            let dims = [2i64];
+            let mut data = [1f32, 2f32];
+            let input =
+                vaccel_tf_tensor_new(1, dims.as_ptr(), vaccel_tf_data_type::VACCEL_TF_FLOAT);
+            assert_eq!(
+                vaccel_tf_tensor_set_data(input, data.as_mut_ptr() as *mut c_void, 8),
+                VACCEL_OK
+            );
+            assert_eq!(
+                vaccel_tf_tensor_get_data(input),
+                data.as_mut_ptr() as *mut c_void
+            );
+            let run_options = vaccel_tf_buffer {
+                data: ptr::null_mut(),
+                size: 0,
+            };
+            let mut output = ptr::null_mut();
+            assert_eq!(
+                vaccel_tf_session_run(
+                    &mut sess,
+                    model,
+                    &run_options,
+                    &in_node,
+                    &input,
+                    1,
+                    &in_node,
+                    &mut output,
+                    1,
+                    &mut status,
+                ),
+                VACCEL_OK
+            );
+            let output_data = slice::from_raw_parts((*output).data as *const u8, (*output).size);
+            assert_eq!(
+                output_data,
+                slice::from_raw_parts(data.as_ptr() as *const u8, 8)
+            );
+            assert_eq!(*(*output).dims, 2);
+            assert_eq!((*output).data_type, vaccel_tf_data_type::VACCEL_TF_FLOAT);
+            assert_eq!(vaccel_tf_tensor_destroy(output), VACCEL_OK);
+            // The data set by the caller is left alone
+            assert_eq!(vaccel_tf_tensor_destroy(input), VACCEL_OK);
+            assert_eq!(data, [1f32, 2f32]);
+
+            let mut options = [0u8; 4];
+            let run_options = vaccel_tf_buffer {
+                data: options.as_mut_ptr() as *mut c_void,
+                size: options.len(),
+            };
+            let out_node = vaccel_tf_node_new(name.as_ptr(), 0);
+            assert_eq!(CStr::from_ptr((*out_node).name), name.as_c_str());
+            assert_eq!(
+                vaccel_tf_session_run(
+                    &mut sess,
+                    model,
+                    &run_options,
+                    ptr::null(),
+                    ptr::null(),
+                    0,
+                    out_node,
+                    &mut output,
+                    1,
+                    &mut status,
+                ),
+                VACCEL_ENOTSUP
+            );
+            assert_eq!(vaccel_tf_node_destroy(out_node), VACCEL_OK);
+
+            let output = vaccel_tf_tensor_allocate(
+                1,
+                dims.as_ptr(),
+                vaccel_tf_data_type::VACCEL_TF_FLOAT,
+                8,
+            );
+            assert_eq!((*output).size, 8);
+            assert_eq!(*((*output).data as *const u8), 0);
+            assert_eq!(vaccel_tf_tensor_destroy(output), VACCEL_OK);
+
+            // Loaded models cannot be released
+            assert_ne!(vaccel_resource_destroy(&mut res), VACCEL_OK);
+            assert_eq!(
+                vaccel_tf_session_delete(&mut sess, model, &mut status),
+                VACCEL_OK
+            );
+
+            assert_eq!(vaccel_sess_unregister(&mut sess, &mut res), VACCEL_OK);
+            assert_eq!(vaccel_resource_destroy(&mut res), VACCEL_OK);
+            assert_eq!(vaccel_tf_saved_model_id(model), 0);
+            assert_eq!(
+                vaccel_tf_session_load(&mut sess, model, &mut status),
+                VACCEL_EINVAL
+            );
+            assert_eq!(vaccel_tf_saved_model_destroy(model), VACCEL_OK);
+            assert_eq!(vaccel_sess_free(&mut sess), VACCEL_OK);
+            assert!(sess.priv_.is_null() && sess.resources.is_null());
+        }
+    }
+
+    /// The header checked in under `include/` must match the one generated
+    /// from the sources
+    #[test]
+    fn header_up_to_date() {
+        let generated = concat!(env!("OUT_DIR"), "/vaccel.h");
+        assert!(
+            include_str!(concat!(env!("OUT_DIR"), "/vaccel.h"))
+                == include_str!("../include/vaccel.h"),
+            "include/vaccel.h is out of date, update it with: cp {} vaccel-capi/include/",
+            generated
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::error::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use structopt::StructOpt;
+
+use vaccel::tensorflow::Node;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "vaccel", about = "Drive a vAccel agent from the command line")]
+pub struct VaccelCli {
+    /// URI of the agent: a UNIX socket path, `unix://<path>`,
+    /// `vsock://<cid>:<port>` or `tcp://<host>:<port>`
+    #[structopt(
+        short = "a",
+        long = "address",
+        env = "VACCEL_RPC_ADDRESS",
+        default_value = "vsock://2:2048"
+    )]
+    pub address: String,
+
+    /// Token to authenticate with, if the agent requires one
+    #[structopt(long = "token", env = "VACCEL_RPC_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// PEM bundle of CAs used to authenticate the agent. Enables TLS on TCP
+    /// addresses
+    #[structopt(long = "tls-ca", parse(from_os_str))]
+    pub tls_ca: Option<PathBuf>,
+
+    /// Seconds a request is allowed to take
+    #[structopt(long = "timeout")]
+    pub timeout: Option<u64>,
+
+    #[structopt(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// List the plugins loaded by the agent
+    Plugins,
+
+    /// Register a SavedModel, run inference on it once and unload it
+    Infer {
+        #[structopt(flatten)]
+        model: Model,
+
+        #[structopt(flatten)]
+        tensors: Tensors,
+    },
+
+    /// Read commands from standard input, one per line, and run them over a
+    /// single connection. Sessions and resources are dropped by the agent
+    /// when the connection closes, so this is how to use them across
+    /// commands.
+    Shell,
+}
+
+/// Commands of `vaccel shell`
+#[derive(Debug, StructOpt)]
+#[structopt(name = "")]
+pub enum ShellCommand {
+    /// Create a session
+    SessionNew,
+
+    /// Destroy a session
+    SessionDestroy { session: u64 },
+
+    /// Register a SavedModel, printing its resource id
+    Register {
+        #[structopt(flatten)]
+        model: Model,
+    },
+
+    /// Load a registered model
+    Load { model: u64 },
+
+    /// Unload a loaded model
+    Unload { model: u64 },
+
+    /// Run inference on a loaded model
+    Run {
+        model: u64,
+
+        #[structopt(flatten)]
+        tensors: Tensors,
+    },
+
+    /// List the plugins loaded by the agent
+    Plugins,
+
+    /// Close the connection and exit
+    Exit,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Model {
+    /// Directory of the SavedModel
+    #[structopt(parse(from_os_str))]
+    pub dir: PathBuf,
+
+    /// Pass the path of the directory to the agent instead of its contents.
+    /// The agent must see the same filesystem
+    #[structopt(long = "by-path")]
+    pub by_path: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct Tensors {
+    /// Input node and the `.npy` file holding its tensor, as
+    /// `<name>[:<id>]=<file>`. May be repeated
+    #[structopt(short = "i", long = "input", number_of_values = 1)]
+    pub inputs: Vec<Input>,
+
+    /// Output node to fetch, as `<name>[:<id>][=<file>]`. The tensor is
+    /// written to the `.npy` file if one is given, and printed otherwise.
+    /// May be repeated
+    #[structopt(short = "o", long = "output", number_of_values = 1)]
+    pub outputs: Vec<Output>,
+}
+
+fn parse_node(node: &str) -> Result<Node, Box<dyn Error>> {
+    let (name, id) = match node.rsplit_once(':') {
+        None => (node, 0),
+        Some((name, id)) => (name, id.parse()?),
+    };
+    if name.is_empty() {
+        return Err("empty node name".into());
+    }
+
+    Ok(Node {
+        name: name.to_string(),
+        id,
+    })
+}
+
+#[derive(Debug)]
+pub struct Input {
+    pub node: Node,
+    pub file: PathBuf,
+}
+
+impl FromStr for Input {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (node, file) = s.split_once('=').ok_or("expected <name>[:<id>]=<file>")?;
+
+        Ok(Input {
+            node: parse_node(node)?,
+            file: PathBuf::from(file),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Output {
+    pub node: Node,
+    pub file: Option<PathBuf>,
+}
+
+impl FromStr for Output {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (node, file) = match s.split_once('=') {
+            None => (s, None),
+            Some((node, file)) => (node, Some(PathBuf::from(file))),
+        };
+
+        Ok(Output {
+            node: parse_node(node)?,
+            file,
+        })
+    }
+}
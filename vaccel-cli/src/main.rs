@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::process;
+use std::time::Duration;
+
+use structopt::clap::ErrorKind;
+use structopt::StructOpt;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use vaccel::client::{Endpoint, Vaccel, VaccelConfig};
+use vaccel::resource::Resource;
+use vaccel::session::Session;
+use vaccel::tensorflow::models::TensorflowSavedModelBuilder;
+use vaccel::tensorflow::{DataType, Tensor};
+use vaccel::tls::TlsConfig;
+
+mod cli;
+mod npy;
+
+use cli::{Command, Model, ShellCommand, Tensors, VaccelCli};
+
+/// Most elements of a tensor we print
+const MAX_PRINTED: usize = 16;
+
+async fn connect(cli: &VaccelCli) -> Result<Vaccel, Box<dyn Error>> {
+    let endpoint: Endpoint = cli
+        .address
+        .parse()
+        .map_err(|_| format!("{}: invalid agent address", cli.address))?;
+
+    let mut config = VaccelConfig::new(endpoint);
+    if let Some(ref token) = cli.token {
+        config = config.token(token.clone());
+    }
+    if let Some(ref ca) = cli.tls_ca {
+        config = config.tls(TlsConfig::new(ca.clone()));
+    }
+    if let Some(timeout) = cli.timeout {
+        config = config.timeout(Duration::from_secs(timeout));
+    }
+
+    Vaccel::new(config)
+        .await
+        .map_err(|e| format!("{}: could not connect: {}", cli.address, e).into())
+}
+
+/// Register the SavedModel of `model` with the agent
+async fn register(client: &Vaccel, model: &Model) -> Result<u64, Box<dyn Error>> {
+    let builder = TensorflowSavedModelBuilder::new();
+    let builder = if model.by_path {
+        builder.export_dir(model.dir.clone())
+    } else {
+        let read = |path: &str| {
+            let path = model.dir.join(path);
+            fs::read(&path).map_err(|e| format!("{}: {}", path.display(), e))
+        };
+
+        builder
+            .model(read("saved_model.pb")?)
+            .checkpoint(read("variables/variables.data-00000-of-00001")?)
+            .var_index(read("variables/variables.index")?)
+    };
+
+    let resource = Resource::TensorflowSavedModel(builder.build()?);
+    Ok(client.register_resource(resource).await?)
+}
+
+/// Run inference on the loaded `model`, reading the inputs from and writing
+/// the outputs to the files given in `tensors`
+async fn run(client: &Vaccel, model: u64, tensors: &Tensors) -> Result<(), Box<dyn Error>> {
+    let inputs = tensors
+        .inputs
+        .iter()
+        .map(|input| Ok((input.node.clone(), npy::read(&input.file)?)))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+    let outputs = tensors
+        .outputs
+        .iter()
+        .map(|output| output.node.clone())
+        .collect();
+
+    let results = client.tf_session_run(model, inputs, outputs).await?;
+    for (output, tensor) in tensors.outputs.iter().zip(results) {
+        match output.file {
+            Some(ref path) => npy::write(path, &tensor)?,
+            None => println!(
+                "{}:{} {}",
+                output.node.name,
+                output.node.id,
+                describe(&tensor)
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Shape, element type and leading elements of `tensor`
+fn describe(tensor: &Tensor) -> String {
+    let size = match npy::element_size(tensor.data_type) {
+        None => return format!("{:?} {:?}", tensor.dims, tensor.data_type),
+        Some(size) => size,
+    };
+
+    let elements: Vec<String> = tensor
+        .data
+        .chunks_exact(size)
+        .take(MAX_PRINTED)
+        .map(|bytes| match tensor.data_type {
+            DataType::Float => f32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::Double => f64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::Int8 => (bytes[0] as i8).to_string(),
+            DataType::Int16 => i16::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::Int32 => i32::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::Int64 => i64::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::UInt8 => bytes[0].to_string(),
+            DataType::UInt16 => u16::from_le_bytes(bytes.try_into().unwrap()).to_string(),
+            DataType::Bool => (bytes[0] != 0).to_string(),
+            DataType::String => unreachable!(),
+        })
+        .collect();
+    let more = if tensor.data.len() / size > MAX_PRINTED {
+        ", ..."
+    } else {
+        ""
+    };
+
+    format!(
+        "{:?} {:?} [{}{}]",
+        tensor.dims,
+        tensor.data_type,
+        elements.join(", "),
+        more
+    )
+}
+
+async fn plugins(client: &Vaccel) -> Result<(), Box<dyn Error>> {
+    for plugin in client.plugins().await? {
+        println!("{}", plugin.name);
+        for function in plugin.functions {
+            println!("    {}", function);
+        }
+    }
+
+    Ok(())
+}
+
+async fn infer(client: &Vaccel, model: &Model, tensors: &Tensors) -> Result<(), Box<dyn Error>> {
+    let id = register(client, model).await?;
+    client.tf_session_load(id).await?;
+    let res = run(client, id, tensors).await;
+    client.tf_session_unload(id).await?;
+
+    res
+}
+
+/// Sessions created from the shell, by id
+type Sessions = HashMap<u64, Session>;
+
+async fn shell_command(
+    client: &Vaccel,
+    sessions: &mut Sessions,
+    command: &ShellCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        ShellCommand::SessionNew => {
+            let session = client.new_session().await?;
+            println!("{}", session.id());
+            sessions.insert(session.id(), session);
+        }
+        ShellCommand::SessionDestroy { session } => {
+            let handle = sessions
+                .get(session)
+                .ok_or_else(|| format!("Unknown session {}", session))?;
+            client.destroy_session(handle).await?;
+            sessions.remove(session);
+        }
+        ShellCommand::Register { model } => println!("{}", register(client, model).await?),
+        ShellCommand::Load { model } => client.tf_session_load(*model).await?,
+        ShellCommand::Unload { model } => client.tf_session_unload(*model).await?,
+        ShellCommand::Run { model, tensors } => run(client, *model, tensors).await?,
+        ShellCommand::Plugins => plugins(client).await?,
+        ShellCommand::Exit => {}
+    }
+
+    Ok(())
+}
+
+/// Run the commands read from standard input, until it is closed or `exit`.
+/// Lines are split on whitespace, so paths cannot contain spaces.
+async fn shell(client: &Vaccel) -> Result<(), Box<dyn Error>> {
+    let interactive = io::stdin().is_terminal();
+    let mut sessions = Sessions::new();
+    let mut failed = false;
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    loop {
+        if interactive {
+            print!("vaccel> ");
+            io::stdout().flush()?;
+        }
+
+        let line = match lines.next_line().await? {
+            None => break,
+            Some(line) => line,
+        };
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+
+        let words = std::iter::once("").chain(line.split_whitespace());
+        let command = match ShellCommand::from_iter_safe(words) {
+            Ok(command) => command,
+            Err(e) => {
+                eprintln!("{}", e.message);
+                if !matches!(
+                    e.kind,
+                    ErrorKind::HelpDisplayed | ErrorKind::VersionDisplayed
+                ) {
+                    failed = true;
+                }
+                continue;
+            }
+        };
+        if let ShellCommand::Exit = command {
+            break;
+        }
+
+        if let Err(e) = shell_command(client, &mut sessions, &command).await {
+            eprintln!("Error: {}", e);
+            failed = true;
+        }
+    }
+
+    if failed && !interactive {
+        return Err("Some commands failed".into());
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = VaccelCli::from_args();
+
+    let res = match connect(&cli).await {
+        Ok(client) => match cli.command {
+            Command::Plugins => plugins(&client).await,
+            Command::Infer {
+                ref model,
+                ref tensors,
+            } => infer(&client, model, tensors).await,
+            Command::Shell => shell(&client).await,
+        },
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = res {
+        eprintln!("vaccel: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,231 @@
+//! Reading and writing tensors in the NumPy `.npy` format
+//!
+//! Only little-endian, C-ordered arrays of the element types vAccel knows of
+//! are supported.
+
+use std::convert::{TryFrom, TryInto};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+use vaccel::tensorflow::{DataType, Tensor};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// NumPy type descriptor of `data_type`
+fn descr(data_type: DataType) -> Option<&'static str> {
+    match data_type {
+        DataType::Float => Some("<f4"),
+        DataType::Double => Some("<f8"),
+        DataType::Int8 => Some("|i1"),
+        DataType::Int16 => Some("<i2"),
+        DataType::Int32 => Some("<i4"),
+        DataType::Int64 => Some("<i8"),
+        DataType::UInt8 => Some("|u1"),
+        DataType::UInt16 => Some("<u2"),
+        DataType::Bool => Some("|b1"),
+        DataType::String => None,
+    }
+}
+
+/// Element type described by `descr`
+fn data_type(descr: &str) -> Option<DataType> {
+    match descr {
+        "<f4" => Some(DataType::Float),
+        "<f8" => Some(DataType::Double),
+        "|i1" | "<i1" => Some(DataType::Int8),
+        "<i2" => Some(DataType::Int16),
+        "<i4" => Some(DataType::Int32),
+        "<i8" => Some(DataType::Int64),
+        "|u1" | "<u1" => Some(DataType::UInt8),
+        "<u2" => Some(DataType::UInt16),
+        "|b1" | "<b1" => Some(DataType::Bool),
+        _ => None,
+    }
+}
+
+/// Size in bytes of an element of `data_type`
+pub fn element_size(data_type: DataType) -> Option<usize> {
+    match data_type {
+        DataType::Int8 | DataType::UInt8 | DataType::Bool => Some(1),
+        DataType::Int16 | DataType::UInt16 => Some(2),
+        DataType::Float | DataType::Int32 => Some(4),
+        DataType::Double | DataType::Int64 => Some(8),
+        DataType::String => None,
+    }
+}
+
+/// Value of `key` in the header dictionary, up to the next top-level comma
+fn header_value<'a>(header: &'a str, key: &str) -> Option<&'a str> {
+    let start = header.find(&format!("'{}':", key))? + key.len() + 3;
+    let value = header[start..].trim_start();
+    let end = if value.starts_with('(') {
+        value.find(')')? + 1
+    } else {
+        value.find([',', '}'])?
+    };
+
+    Some(value[..end].trim())
+}
+
+/// Decode a tensor from the contents of a `.npy` file
+pub fn decode(bytes: &[u8]) -> Result<Tensor, Box<dyn Error>> {
+    if bytes.len() < 10 || &bytes[..6] != MAGIC {
+        return Err("not a .npy file".into());
+    }
+
+    let (header_len, offset) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+            12,
+        ),
+        version => return Err(format!("unsupported .npy version {}", version).into()),
+    };
+    let header = bytes
+        .get(offset..offset + header_len)
+        .ok_or("truncated .npy header")?;
+    let header = std::str::from_utf8(header)?;
+
+    let descr = header_value(header, "descr")
+        .ok_or("no 'descr' in .npy header")?
+        .trim_matches('\'');
+    let data_type = data_type(descr).ok_or_else(|| format!("unsupported dtype '{}'", descr))?;
+
+    if header_value(header, "fortran_order") != Some("False") {
+        return Err("Fortran-ordered arrays are not supported".into());
+    }
+
+    let dims = header_value(header, "shape")
+        .ok_or("no 'shape' in .npy header")?
+        .trim_matches(['(', ')'])
+        .split(',')
+        .map(str::trim)
+        .filter(|dim| !dim.is_empty())
+        .map(str::parse)
+        .collect::<Result<Vec<i64>, _>>()?;
+
+    let data = bytes[offset + header_len..].to_vec();
+    let expected = dims
+        .iter()
+        .try_fold(element_size(data_type).unwrap(), |size, &dim| {
+            usize::try_from(dim)
+                .ok()
+                .and_then(|dim| size.checked_mul(dim))
+        })
+        .ok_or("invalid shape in .npy header")?;
+    if data.len() != expected {
+        return Err(format!("expected {} bytes of data, found {}", expected, data.len()).into());
+    }
+
+    Ok(Tensor {
+        dims,
+        data_type,
+        data,
+    })
+}
+
+/// Encode `tensor` in the `.npy` format
+pub fn encode(tensor: &Tensor) -> Result<Vec<u8>, Box<dyn Error>> {
+    let descr = descr(tensor.data_type)
+        .ok_or_else(|| format!("{:?} tensors cannot be stored as .npy", tensor.data_type))?;
+
+    let shape = match tensor.dims.len() {
+        1 => format!("({},)", tensor.dims[0]),
+        _ => {
+            let dims: Vec<String> = tensor.dims.iter().map(|dim| dim.to_string()).collect();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        descr, shape
+    );
+
+    // The data must start on a 64-byte boundary, and the header ends with
+    // a newline
+    let unpadded = MAGIC.len() + 4 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(unpadded + 64 + tensor.data.len());
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&[1, 0]);
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(&tensor.data);
+
+    Ok(bytes)
+}
+
+/// Read a tensor from the `.npy` file at `path`
+pub fn read(path: &Path) -> Result<Tensor, Box<dyn Error>> {
+    let bytes = fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    decode(&bytes).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+/// Write `tensor` to the `.npy` file at `path`
+pub fn write(path: &Path, tensor: &Tensor) -> Result<(), Box<dyn Error>> {
+    fs::write(path, encode(tensor)?).map_err(|e| format!("{}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let tensor = Tensor {
+            dims: vec![2, 3],
+            data_type: DataType::Float,
+            data: (0..6)
+                .flat_map(|x| (x as f32).to_le_bytes().to_vec())
+                .collect(),
+        };
+
+        let bytes = encode(&tensor).unwrap();
+        assert_eq!(&bytes[..6], MAGIC);
+        assert_eq!((bytes.len() - tensor.data.len()) % 64, 0);
+        assert_eq!(decode(&bytes).unwrap(), tensor);
+
+        let scalar = Tensor {
+            dims: vec![],
+            data_type: DataType::Bool,
+            data: vec![1],
+        };
+        assert_eq!(decode(&encode(&scalar).unwrap()).unwrap(), scalar);
+    }
+
+    #[test]
+    fn numpy_header() {
+        // As written by `np.save(f, np.arange(3, dtype='<i8'))`
+        let mut bytes = b"\x93NUMPY\x01\x00v\x00".to_vec();
+        let mut header = "{'descr': '<i8', 'fortran_order': False, 'shape': (3,), }".to_string();
+        header.push_str(&" ".repeat(118 - header.len() - 1));
+        header.push('\n');
+        bytes.extend_from_slice(header.as_bytes());
+        for x in 0i64..3 {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+
+        let tensor = decode(&bytes).unwrap();
+        assert_eq!(tensor.dims, vec![3]);
+        assert_eq!(tensor.data_type, DataType::Int64);
+
+        bytes[21] = b'>';
+        assert!(decode(&bytes).is_err());
+        bytes[21] = b'<';
+
+        // Shapes that are negative, or too large to address, are refused
+        for shape in ["(-3,)", "(9223372036854775807, 2)"] {
+            let header = format!(
+                "{{'descr': '<i8', 'fortran_order': False, 'shape': {}, }}",
+                shape
+            );
+            let mut bytes = b"\x93NUMPY\x01\x00".to_vec();
+            bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(header.as_bytes());
+            assert!(decode(&bytes).is_err(), "{}", shape);
+        }
+    }
+}
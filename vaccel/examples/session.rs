@@ -1,14 +1,14 @@
 use env_logger::Env;
 use log::info;
 
-use vaccel::client::{Vaccel, VaccelConfig};
+use vaccel::client::{Endpoint, Vaccel};
 
 #[tokio::main]
 async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
 
     info!("Connecting to vsock://2:2048");
-    let client = Vaccel::new(VaccelConfig::Vsock(2, 2048))
+    let client = Vaccel::new(Endpoint::Vsock(2, 2048))
         .await
         .expect("Could not create client");
 
@@ -1,18 +1,31 @@
+use std::path::PathBuf;
+
 use env_logger::Env;
 use log::{error, info};
 
-use vaccel::client::{Vaccel, VaccelConfig};
+use vaccel::client::{Endpoint, Vaccel};
+use vaccel::resource::Resource;
+use vaccel::tensorflow::models::TensorflowSavedModelBuilder;
 
 #[tokio::main]
 async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();
 
     info!("Creating client");
-    let client = Vaccel::new(VaccelConfig::Local)
+    let client = Vaccel::new(Endpoint::Local)
         .await
         .expect("Could not create client");
 
-    match client.tf_session_load(1).await {
+    let model = TensorflowSavedModelBuilder::new()
+        .export_dir(PathBuf::from("/path/to/saved_model"))
+        .build()
+        .expect("Could not create model");
+    let model_id = client
+        .register_resource(Resource::TensorflowSavedModel(model))
+        .await
+        .expect("Could not register model");
+
+    match client.tf_session_load(model_id).await {
         Ok(()) => info!("Loaded model"),
         Err(e) => error!("{}", e),
     }
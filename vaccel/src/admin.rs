@@ -0,0 +1,224 @@
+//! Administrative API of the vAccel agent
+//!
+//! Operators use it to inspect what a running agent holds and to reclaim
+//! sessions on behalf of misbehaving clients. It is served apart from the
+//! vAccel API, on a socket only privileged users can reach.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tarpc::serde_transport;
+use tarpc::server::{BaseChannel, Channel};
+use tarpc::{client, context::Context, ClientMessage, Response, Transport};
+use tokio::net::UnixStream;
+use tokio_serde::formats::Json;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use crate::quota::{Principal, Quota, Usage};
+use crate::server::Server;
+use crate::Result;
+
+/// A session held by the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    /// Connection that created the session, if still open
+    pub connection: Option<u64>,
+    /// Client at the other end of `connection`
+    pub peer: Option<String>,
+    pub rundir: Option<PathBuf>,
+}
+
+/// A resource registered with the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceInfo {
+    pub id: u64,
+    /// Type of the resource, e.g. `TensorflowSavedModel`
+    pub kind: String,
+    /// Bytes the resource holds in memory
+    pub size: usize,
+    /// Connection that registered the resource, if still open
+    pub connection: Option<u64>,
+    /// Whether the resource is loaded as a model
+    pub loaded: bool,
+}
+
+/// A plugin loaded by the agent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginInfo {
+    pub name: String,
+    /// Library the plugin was loaded from
+    pub path: PathBuf,
+    /// Functions the plugin implements
+    pub functions: Vec<String>,
+}
+
+/// What a principal holds, and the quota enforced on it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountInfo {
+    pub principal: Principal,
+    pub quota: Quota,
+    pub usage: Usage,
+}
+
+#[tarpc::service]
+pub trait AdminAPI {
+    /// Sessions held by the agent
+    async fn sessions() -> Vec<SessionInfo>;
+
+    /// Resources registered with the agent
+    async fn resources() -> Vec<ResourceInfo>;
+
+    /// Plugins loaded by the agent
+    async fn plugins() -> Vec<PluginInfo>;
+
+    /// Usage of every principal that connected to the agent
+    async fn accounts() -> Vec<AccountInfo>;
+
+    /// Destroy a session, whoever owns it. The owner is notified with
+    /// `Event::SessionReaped`.
+    async fn destroy_session(session: u64) -> Result<()>;
+
+    /// Load all plugins again, from the libraries already in memory. Clients
+    /// are notified with `Event::PluginReloaded` and have to load their
+    /// models again.
+    async fn reload_plugins() -> Result<()>;
+}
+
+#[derive(Clone)]
+struct Admin(Server);
+
+#[tarpc::server]
+impl AdminAPI for Admin {
+    async fn sessions(self, _: Context) -> Vec<SessionInfo> {
+        self.0.session_info()
+    }
+
+    async fn resources(self, _: Context) -> Vec<ResourceInfo> {
+        self.0.resource_info()
+    }
+
+    async fn plugins(self, _: Context) -> Vec<PluginInfo> {
+        self.0.plugin_info()
+    }
+
+    async fn accounts(self, _: Context) -> Vec<AccountInfo> {
+        self.0.account_info()
+    }
+
+    async fn destroy_session(self, _: Context, session: u64) -> Result<()> {
+        self.0.reap_session(session)
+    }
+
+    async fn reload_plugins(self, _: Context) -> Result<()> {
+        self.0.reload_plugins()
+    }
+}
+
+impl Server {
+    /// Serve the administrative API over `transport`, until the client goes
+    /// away. Only privileged clients should be able to reach it.
+    pub async fn serve_admin<T>(self, transport: T)
+    where
+        T: Transport<Response<AdminAPIResponse>, ClientMessage<AdminAPIRequest>> + Send + 'static,
+    {
+        BaseChannel::with_defaults(transport)
+            .execute(Admin(self).serve())
+            .await;
+    }
+}
+
+/// Connect to the administrative API of an agent, listening on the UNIX
+/// socket at `path`
+pub async fn connect(path: &Path) -> Result<AdminAPIClient> {
+    let stream = UnixStream::connect(path).await?;
+    let transport = serde_transport::new(
+        Framed::new(stream, LengthDelimitedCodec::new()),
+        Json::default(),
+    );
+
+    Ok(AdminAPIClient::new(client::Config::default(), transport).spawn())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tarpc::context;
+
+    use crate::resource::Resource;
+    use crate::server::{Peer, VaccelAPI};
+    use crate::tensorflow::models::TensorflowSavedModelBuilder;
+    use crate::Error;
+
+    #[tokio::test]
+    async fn inspect_and_reap() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Vsock { cid: 3 });
+        let session = connection
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        let model = TensorflowSavedModelBuilder::new()
+            .model(vec![0; 4])
+            .checkpoint(vec![0; 4])
+            .var_index(vec![0; 4])
+            .build()
+            .unwrap();
+        let model_id = connection
+            .clone()
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .expect("Could not register resource");
+        connection
+            .clone()
+            .tf_session_load(context::current(), model_id)
+            .await
+            .expect("Could not load model");
+
+        let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+        tokio::spawn(server.clone().serve_admin(server_transport));
+        let admin = AdminAPIClient::new(client::Config::default(), client_transport).spawn();
+
+        let sessions = admin.sessions(context::current()).await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, session);
+        assert_eq!(sessions[0].connection, Some(connection.id()));
+        assert_eq!(sessions[0].peer.as_deref(), Some("vsock(cid=3)"));
+        assert!(sessions[0].rundir.is_some());
+
+        let resources = admin.resources(context::current()).await.unwrap();
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].kind, "TensorflowSavedModel");
+        assert_eq!(resources[0].size, 12);
+        assert!(resources[0].loaded);
+
+        let plugins = admin.plugins(context::current()).await.unwrap();
+        assert_eq!(plugins[0].name, "vaccel-noop");
+        assert!(plugins[0].functions.contains(&"TFSessionRun".to_string()));
+
+        admin
+            .destroy_session(context::current(), session)
+            .await
+            .unwrap()
+            .expect("Could not destroy session");
+        assert!(server.get_session(&session).is_none());
+        match admin.destroy_session(context::current(), session).await {
+            Ok(Err(Error::InvalidArgument)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Models are unloaded along with the plugins they were loaded
+        // through
+        admin
+            .reload_plugins(context::current())
+            .await
+            .unwrap()
+            .expect("Could not reload plugins");
+        let resources = admin.resources(context::current()).await.unwrap();
+        assert!(!resources[0].loaded);
+        let accounts = admin.accounts(context::current()).await.unwrap();
+        assert_eq!(accounts[0].usage.loaded_models, 0);
+    }
+}
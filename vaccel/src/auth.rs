@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use ring::digest::{self, SHA256};
+use serde::{Deserialize, Serialize};
+
+use crate::{Error, Result};
+
+/// Groups of vAccel operations a tenant can be granted access to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Permission {
+    /// Create and destroy sessions
+    Sessions,
+    /// Register resources
+    Resources,
+    /// Load, run and unload TensorFlow models
+    Tensorflow,
+}
+
+const ALL_PERMISSIONS: &[Permission] = &[
+    Permission::Sessions,
+    Permission::Resources,
+    Permission::Tensorflow,
+];
+
+impl FromStr for Permission {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sessions" => Ok(Permission::Sessions),
+            "resources" => Ok(Permission::Resources),
+            "tensorflow" => Ok(Permission::Tensorflow),
+            _ => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// An authenticated client identity
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Tenant {
+    name: String,
+    permissions: Vec<Permission>,
+}
+
+impl Tenant {
+    pub fn new(name: String, permissions: Vec<Permission>) -> Self {
+        Tenant { name, permissions }
+    }
+
+    /// The identity of clients of an agent that does not require
+    /// authentication
+    pub(crate) fn anonymous() -> Self {
+        Tenant::new("anonymous".to_string(), ALL_PERMISSIONS.to_vec())
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn permissions(&self) -> &[Permission] {
+        &self.permissions
+    }
+
+    pub fn allows(&self, permission: Permission) -> bool {
+        self.permissions.contains(&permission)
+    }
+}
+
+/// The tokens accepted by the agent, each mapped to a tenant
+///
+/// Token files contain one token per line, followed by the tenant name and
+/// a comma separated list of permissions (or `all`):
+///
+/// ```text
+/// # token                           tenant   permissions
+/// 3f6c9a0e2b1d4c8f9e7a5b3c1d0e2f4a  guest-1  sessions,resources,tensorflow
+/// 8a7b6c5d4e3f2a1b0c9d8e7f6a5b4c3d  guest-2  all
+/// ```
+#[derive(Debug, Default)]
+pub struct TokenStore {
+    /// Tenants by the SHA-256 digest of their token, so that looking a
+    /// token up does not leak how much of it matched a known one
+    tokens: HashMap<Vec<u8>, Tenant>,
+}
+
+fn token_digest(token: &str) -> Vec<u8> {
+    digest::digest(&SHA256, token.as_bytes()).as_ref().to_vec()
+}
+
+impl TokenStore {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        fs::read_to_string(path)?.parse()
+    }
+
+    pub fn authenticate(&self, token: &str) -> Option<&Tenant> {
+        self.tokens.get(&token_digest(token))
+    }
+}
+
+impl FromStr for TokenStore {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut tokens = HashMap::new();
+
+        for (n, line) in s.lines().map(str::trim).enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid =
+                |reason: String| Error::InvalidTokenFile(format!("line {}: {}", n + 1, reason));
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (token, name, permissions) = match fields[..] {
+                [token, name, permissions] => (token, name, permissions),
+                _ => {
+                    return Err(invalid(format!(
+                        "expected token, tenant and permissions, found {} fields",
+                        fields.len()
+                    )))
+                }
+            };
+
+            let permissions = match permissions {
+                "all" => ALL_PERMISSIONS.to_vec(),
+                _ => permissions
+                    .split(',')
+                    .map(|permission| {
+                        permission
+                            .parse()
+                            .map_err(|_| invalid(format!("unknown permission '{}'", permission)))
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            };
+
+            let tenant = Tenant::new(name.to_string(), permissions);
+            if tokens.insert(token_digest(token), tenant).is_some() {
+                return Err(invalid(format!("duplicate token for tenant '{}'", name)));
+            }
+        }
+
+        Ok(TokenStore { tokens })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn malformed_token_file() {
+        let tokens: TokenStore = "# comment\nsecret guest all\n".parse().unwrap();
+        assert_eq!(tokens.authenticate("secret").unwrap().name(), "guest");
+        assert!(tokens.authenticate("secre").is_none());
+
+        for (contents, reason) in [
+            (
+                "secret guest\n",
+                "line 1: expected token, tenant and permissions",
+            ),
+            (
+                "\nsecret guest sessions,gpu\n",
+                "line 2: unknown permission 'gpu'",
+            ),
+        ] {
+            match contents.parse::<TokenStore>() {
+                Err(Error::InvalidTokenFile(e)) => assert!(e.starts_with(reason), "{}", e),
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+    }
+}
@@ -0,0 +1,134 @@
+//! Batched vAccel operations
+//!
+//! A batch carries an ordered list of operations that the agent executes in
+//! a single request, saving a round-trip per operation. Operations may refer
+//! to sessions and resources created by earlier operations of the same
+//! batch through `Ref::Output`.
+
+use serde::{Deserialize, Serialize};
+
+use crate::resource::Resource;
+use crate::tensorflow::{Node, Tensor};
+use crate::{Error, Result};
+
+/// Refers to a session or a resource an operation acts upon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Ref {
+    /// A session or resource that already exists
+    Id(u64),
+    /// The session or resource created by the operation at this index of
+    /// the batch
+    Output(usize),
+}
+
+impl From<u64> for Ref {
+    fn from(id: u64) -> Self {
+        Ref::Id(id)
+    }
+}
+
+impl Ref {
+    /// The session id this reference stands for, given the results of the
+    /// operations executed so far
+    pub(crate) fn session(self, results: &[Result<Output>]) -> Result<u64> {
+        self.resolve(results, |output| match output {
+            Output::Session(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// The resource id this reference stands for, given the results of the
+    /// operations executed so far
+    pub(crate) fn resource(self, results: &[Result<Output>]) -> Result<u64> {
+        self.resolve(results, |output| match output {
+            Output::Resource(id) => Some(*id),
+            _ => None,
+        })
+    }
+
+    /// Resolve through `id`, which yields the id an output carries if it
+    /// is of the expected kind
+    fn resolve<F>(self, results: &[Result<Output>], id: F) -> Result<u64>
+    where
+        F: FnOnce(&Output) -> Option<u64>,
+    {
+        match self {
+            Ref::Id(id) => Ok(id),
+            Ref::Output(index) => match results.get(index) {
+                Some(Ok(output)) => id(output).ok_or(Error::InvalidArgument),
+                _ => Err(Error::InvalidArgument),
+            },
+        }
+    }
+}
+
+/// A single operation of a batch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Operation {
+    /// Create a new session
+    NewSession,
+    /// Destroy a session
+    DestroySession(Ref),
+    /// Register a new resource
+    RegisterResource(Resource),
+    /// Load a TensorFlow model
+    TfSessionLoad(Ref),
+    /// Run inference on a loaded TensorFlow model
+    TfSessionRun {
+        model: Ref,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    },
+    /// Unload a TensorFlow model
+    TfSessionUnload(Ref),
+}
+
+impl Operation {
+    /// The same operation, made to fail without side effects by referring
+    /// to its own output at `index` of the batch, which never resolves
+    pub(crate) fn unresolvable(self, index: usize) -> Self {
+        match self {
+            Operation::DestroySession(_) => Operation::DestroySession(Ref::Output(index)),
+            Operation::TfSessionLoad(_) => Operation::TfSessionLoad(Ref::Output(index)),
+            Operation::TfSessionRun {
+                inputs, outputs, ..
+            } => Operation::TfSessionRun {
+                model: Ref::Output(index),
+                inputs,
+                outputs,
+            },
+            Operation::TfSessionUnload(_) => Operation::TfSessionUnload(Ref::Output(index)),
+            operation => operation,
+        }
+    }
+}
+
+/// The outcome of a successful operation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Output {
+    /// Id of the session created by `Operation::NewSession`
+    Session(u64),
+    /// Id of the resource registered by `Operation::RegisterResource`
+    Resource(u64),
+    /// Tensors returned by `Operation::TfSessionRun`
+    Tensors(Vec<Tensor>),
+    /// The operation completed without a result
+    Done,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_expected_kind() {
+        let results = vec![Ok(Output::Session(3)), Ok(Output::Resource(5))];
+
+        assert_eq!(Ref::Output(0).session(&results).unwrap(), 3);
+        assert_eq!(Ref::Output(1).resource(&results).unwrap(), 5);
+        assert!(Ref::Output(0).resource(&results).is_err());
+        assert!(Ref::Output(1).session(&results).is_err());
+        assert!(Ref::Output(2).session(&results).is_err());
+        assert_eq!(Ref::Id(7).resource(&results).unwrap(), 7);
+    }
+}
@@ -0,0 +1,215 @@
+//! A blocking vAccel client
+//!
+//! `blocking::Vaccel` wraps `client::Vaccel` and drives it on a runtime it
+//! owns, so it can be used from plain synchronous code. It must not be used
+//! from within an async runtime, as blocking there would stall the runtime.
+
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::{Stream, StreamExt};
+use tokio::runtime::{self, Runtime};
+
+use crate::batch::{Operation, Output};
+use crate::client::{self, CancellationToken, VaccelConfig};
+use crate::event::Event;
+use crate::job::JobStatus;
+use crate::resource::Resource;
+use crate::server::Plugin;
+use crate::session::Session;
+use crate::tensorflow::{Node, Tensor};
+use crate::Result;
+
+#[derive(Debug, Clone)]
+pub struct Vaccel {
+    inner: client::Vaccel,
+    runtime: Arc<Runtime>,
+}
+
+impl Vaccel {
+    pub fn new<C: Into<VaccelConfig>>(config: C) -> Result<Self> {
+        // The connection is driven by background tasks, so we need at least
+        // one worker that keeps running while the caller is not blocked on us
+        let runtime = runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("vaccel-blocking")
+            .enable_all()
+            .build()?;
+
+        let inner = runtime.block_on(client::Vaccel::new(config))?;
+
+        Ok(Self {
+            inner,
+            runtime: Arc::new(runtime),
+        })
+    }
+
+    /// See `client::Vaccel::with_timeout`
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            inner: self.inner.with_timeout(timeout),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// See `client::Vaccel::with_cancellation`
+    pub fn with_cancellation(&self, token: CancellationToken) -> Self {
+        Self {
+            inner: self.inner.with_cancellation(token),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    pub fn server_features(&self) -> Vec<String> {
+        self.inner.server_features()
+    }
+
+    pub fn new_session(&self) -> Result<Session> {
+        self.runtime.block_on(self.inner.new_session())
+    }
+
+    pub fn destroy_session(&self, session: &Session) -> Result<()> {
+        self.runtime.block_on(self.inner.destroy_session(session))
+    }
+
+    pub fn register_resource(&self, resource: Resource) -> Result<u64> {
+        self.runtime
+            .block_on(self.inner.register_resource(resource))
+    }
+
+    pub fn unregister_resource(&self, id: u64) -> Result<()> {
+        self.runtime.block_on(self.inner.unregister_resource(id))
+    }
+
+    pub fn tf_session_load(&self, model_id: u64) -> Result<()> {
+        self.runtime.block_on(self.inner.tf_session_load(model_id))
+    }
+
+    pub fn tf_session_unload(&self, model_id: u64) -> Result<()> {
+        self.runtime
+            .block_on(self.inner.tf_session_unload(model_id))
+    }
+
+    pub fn tf_session_run(
+        &self,
+        model_id: u64,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    ) -> Result<Vec<Tensor>> {
+        self.runtime
+            .block_on(self.inner.tf_session_run(model_id, inputs, outputs))
+    }
+
+    /// See `client::Vaccel::plugins`
+    pub fn plugins(&self) -> Result<Vec<Plugin>> {
+        self.runtime.block_on(self.inner.plugins())
+    }
+
+    /// See `client::Vaccel::submit_batch`
+    pub fn submit_batch(
+        &self,
+        operations: Vec<Operation>,
+        stop_on_error: bool,
+    ) -> Result<Vec<Result<Output>>> {
+        self.runtime
+            .block_on(self.inner.submit_batch(operations, stop_on_error))
+    }
+
+    /// See `client::Vaccel::submit_job`
+    pub fn submit_job(&self, session: &Session, operation: Operation) -> Result<JobHandle> {
+        let inner = self
+            .runtime
+            .block_on(self.inner.submit_job(session, operation))?;
+
+        Ok(JobHandle {
+            inner,
+            runtime: self.runtime.clone(),
+        })
+    }
+
+    /// See `client::Vaccel::events`
+    pub fn events(&self) -> Result<Events> {
+        let stream = self.runtime.block_on(self.inner.events())?;
+
+        Ok(Events {
+            stream: Box::pin(stream),
+            runtime: self.runtime.clone(),
+        })
+    }
+}
+
+/// Iterator over the events pushed by the agent. See `client::Vaccel::events`
+pub struct Events {
+    stream: Pin<Box<dyn Stream<Item = Result<Event>> + Send>>,
+    runtime: Arc<Runtime>,
+}
+
+impl Iterator for Events {
+    type Item = Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.runtime.block_on(self.stream.next())
+    }
+}
+
+/// See `client::JobHandle`
+#[derive(Debug)]
+pub struct JobHandle {
+    inner: client::JobHandle,
+    runtime: Arc<Runtime>,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.inner.id()
+    }
+
+    pub fn poll(&mut self) -> Result<JobStatus> {
+        self.runtime.block_on(self.inner.poll())
+    }
+
+    pub fn wait(self) -> Result<Output> {
+        self.runtime.block_on(self.inner.wait())
+    }
+
+    pub fn cancel(self) -> Result<()> {
+        self.runtime.block_on(self.inner.cancel())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::PathBuf;
+
+    use crate::client::Endpoint;
+    use crate::tensorflow::models::TensorflowSavedModelBuilder;
+
+    #[test]
+    fn blocking_client() {
+        let client = Vaccel::new(Endpoint::Local).expect("Could not create client");
+
+        let session = client.new_session().expect("Could not create session");
+        assert_eq!(session.id(), 1);
+
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let model_id = client
+            .register_resource(Resource::TensorflowSavedModel(model))
+            .expect("Could not register model");
+        client
+            .tf_session_load(model_id)
+            .expect("Could not load model");
+        client
+            .tf_session_unload(model_id)
+            .expect("Could not unload model");
+
+        client
+            .destroy_session(&session)
+            .expect("Could not destroy session");
+    }
+}
@@ -1,86 +1,1044 @@
+use std::cmp;
+use std::collections::VecDeque;
+use std::future::{Future, IntoFuture};
+use std::io::{self, ErrorKind};
 use std::path::PathBuf;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
 
+use dashmap::DashMap;
+use futures::stream::{self, Stream};
+use log::{debug, warn};
+
+use tarpc::client;
+use tarpc::context::{self, Context};
 use tarpc::serde_transport;
-use tarpc::server::{BaseChannel, Channel};
 use tarpc::transport::channel;
-use tarpc::{client, context};
 
-use tokio::net::UnixStream;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpStream, UnixStream};
+use tokio::sync::Mutex;
 use tokio_serde::formats::Json;
 use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tokio_vsock::VsockStream;
 
-use crate::server::{Server, VaccelAPI, VaccelAPIClient};
+use crate::batch::{Operation, Output, Ref};
+use crate::event::Event;
+use crate::job::JobStatus;
+use crate::resource::Resource;
+use crate::server::{
+    Hello, Peer, Plugin, Server, VaccelAPIClient, FEATURE_BATCH, FEATURE_EVENTS, FEATURE_JOBS,
+    FEATURE_PLUGINS, FEATURE_TF_SESSION_RUN, FEATURE_UNREGISTER_RESOURCE, PROTOCOL_VERSION,
+};
 use crate::session::Session;
-use crate::Result;
+use crate::tensorflow::{Node, Tensor};
+use crate::tls::TlsConfig;
+use crate::{Error, Result};
+
+pub use tokio_util::sync::CancellationToken;
 
-pub enum VaccelConfig {
+/// The transport used to reach the vAccel agent
+#[derive(Debug, Clone)]
+pub enum Endpoint {
     /// In-memory handling of vAccel requests
     Local,
     /// Request handling over a vsock socket
     Vsock(u32, u32),
     /// Request handling over a UNIX socket
     Unix(PathBuf),
+    /// Request handling over TCP, given as `host:port`
+    Tcp(String),
+}
+
+impl FromStr for Endpoint {
+    type Err = Error;
+
+    /// Parse an endpoint URI: `local`, `unix://<path>`, `vsock://<cid>:<port>`
+    /// or `tcp://<host>:<port>`. A plain path is treated as a UNIX socket.
+    fn from_str(uri: &str) -> Result<Self> {
+        if uri == "local" {
+            return Ok(Endpoint::Local);
+        }
+
+        match uri.split_once("://") {
+            None => Ok(Endpoint::Unix(PathBuf::from(uri))),
+            Some(("unix", path)) => Ok(Endpoint::Unix(PathBuf::from(path))),
+            Some(("tcp", addr)) => Ok(Endpoint::Tcp(addr.to_string())),
+            Some(("vsock", addr)) => {
+                let (cid, port) = addr.split_once(':').ok_or(Error::InvalidArgument)?;
+                let cid = cid.parse().map_err(|_| Error::InvalidArgument)?;
+                let port = port.parse().map_err(|_| Error::InvalidArgument)?;
+                Ok(Endpoint::Vsock(cid, port))
+            }
+            Some(_) => Err(Error::InvalidArgument),
+        }
+    }
+}
+
+/// Controls how a `Vaccel` client recovers from a lost connection
+///
+/// Requests that may already have run on the agent when the connection
+/// dropped, such as creating sessions or resources, running models or
+/// submitting jobs and batches, are not retried: they fail with
+/// `Error::Disconnected` once the connection has been re-established.
+#[derive(Debug, Clone)]
+pub struct ReconnectPolicy {
+    /// Number of connection attempts before giving up
+    pub max_attempts: u32,
+    /// Delay before the first retry. It is doubled after every failed attempt
+    pub initial_backoff: Duration,
+    /// Upper bound for the delay between two attempts
+    pub max_backoff: Duration,
+    /// Re-create sessions and re-register resources on the new connection
+    pub replay: bool,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            replay: true,
+        }
+    }
+}
+
+/// Deadline used for requests when none is configured
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Shortest time to wait for in a single request when waiting for jobs or
+/// events, so that short timeouts do not make the client spin
+const MIN_WAIT_SLICE: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone)]
+pub struct VaccelConfig {
+    endpoint: Endpoint,
+    reconnect: Option<ReconnectPolicy>,
+    timeout: Duration,
+    tls: Option<TlsConfig>,
+    token: Option<String>,
+}
+
+impl VaccelConfig {
+    pub fn new(endpoint: Endpoint) -> Self {
+        VaccelConfig {
+            endpoint,
+            reconnect: None,
+            timeout: DEFAULT_TIMEOUT,
+            tls: None,
+            token: None,
+        }
+    }
+
+    /// Authenticate with the agent using `token` right after connecting
+    pub fn token(mut self, token: String) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Use TLS on top of a TCP endpoint. Connecting to any other endpoint
+    /// fails with `Error::InvalidArgument`.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Default time a request is allowed to take before failing with
+    /// `Error::DeadlineExceeded`
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Reconnect transparently, following `policy`, when the connection drops
+    pub fn reconnect(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = Some(policy);
+        self
+    }
+}
+
+impl From<Endpoint> for VaccelConfig {
+    fn from(endpoint: Endpoint) -> Self {
+        VaccelConfig::new(endpoint)
+    }
+}
+
+#[derive(Debug)]
+struct Connection {
+    generation: u64,
+    client: VaccelAPIClient,
+    /// What the agent told us about itself when connecting
+    server: Hello,
+}
+
+/// A resource registered through this client. The resource itself is only
+/// kept around when it needs to be replayed after a reconnect.
+#[derive(Debug)]
+struct RegisteredResource {
+    resource: Option<Resource>,
+    remote: Option<u64>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: VaccelConfig,
+    connection: RwLock<Connection>,
+    reconnecting: Mutex<()>,
+    session_id: AtomicU64,
+    /// Sessions created by this client, mapped to their id on the agent side.
+    /// `None` means the session could not be re-established after a reconnect.
+    sessions: DashMap<u64, Option<u64>>,
+    resource_id: AtomicU64,
+    resources: DashMap<u64, RegisteredResource>,
 }
 
 #[derive(Debug, Clone)]
 pub struct Vaccel {
-    inner: VaccelAPIClient,
+    inner: Arc<Inner>,
+    timeout: Duration,
+    cancellation: Option<CancellationToken>,
+}
+
+/// Translate an error of the transport an RPC went through. Errors of the
+/// requests themselves come back from the agent as `Error` instead.
+fn transport(err: io::Error) -> Error {
+    match err.kind() {
+        ErrorKind::TimedOut => Error::DeadlineExceeded,
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::BrokenPipe
+        | ErrorKind::NotConnected
+        | ErrorKind::UnexpectedEof => Error::Disconnected,
+        _ => Error::IOError(err.to_string()),
+    }
+}
+
+fn spawn_client<S>(stream: S) -> VaccelAPIClient
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let transport = serde_transport::new(
+        Framed::new(stream, LengthDelimitedCodec::new()),
+        Json::default(),
+    );
+
+    VaccelAPIClient::new(client::Config::default(), transport).spawn()
+}
+
+async fn connect(config: &VaccelConfig) -> Result<(VaccelAPIClient, Hello)> {
+    let client = open(config).await?;
+
+    let hello = Hello {
+        version: PROTOCOL_VERSION,
+        features: Vec::new(),
+    };
+    let server = client
+        .hello(context::current(), hello)
+        .await
+        .map_err(transport)??;
+    debug!(
+        "Agent speaks protocol version {} with features {:?}",
+        server.version, server.features
+    );
+
+    if let Some(ref token) = config.token {
+        let tenant = client
+            .authenticate(context::current(), token.clone())
+            .await
+            .map_err(transport)??;
+        debug!("Authenticated as {}", tenant.name());
+    }
+
+    Ok((client, server))
+}
+
+async fn open(config: &VaccelConfig) -> Result<VaccelAPIClient> {
+    // Only TCP connections are encrypted, so refuse to silently go without
+    if config.tls.is_some() && !matches!(config.endpoint, Endpoint::Tcp(_)) {
+        warn!("TLS is only supported on TCP endpoints");
+        return Err(Error::InvalidArgument);
+    }
+
+    match config.endpoint {
+        Endpoint::Local => {
+            let (client_transport, server_transport) = channel::unbounded();
+            tokio::spawn(Server::new()?.serve(server_transport, Peer::Local));
+
+            Ok(VaccelAPIClient::new(client::Config::default(), client_transport).spawn())
+        }
+        Endpoint::Vsock(cid, port) => Ok(spawn_client(VsockStream::connect(cid, port).await?)),
+        Endpoint::Unix(ref path) => Ok(spawn_client(UnixStream::connect(path).await?)),
+        Endpoint::Tcp(ref addr) => {
+            let stream = TcpStream::connect(addr).await?;
+            match config.tls {
+                None => Ok(spawn_client(stream)),
+                Some(ref tls) => {
+                    let stream = tls
+                        .connector()?
+                        .connect(tls.server_name_for(addr)?, stream)
+                        .await?;
+                    Ok(spawn_client(stream))
+                }
+            }
+        }
+    }
 }
 
 impl Vaccel {
-    pub async fn new(config: VaccelConfig) -> Result<Self> {
-        match config {
-            VaccelConfig::Local => {
-                let (client_transport, server_transport) = channel::unbounded();
-                let server = BaseChannel::with_defaults(server_transport);
-                tokio::spawn(server.execute(Server::new()?.serve()));
-
-                Ok(Self {
-                    inner: VaccelAPIClient::new(client::Config::default(), client_transport)
-                        .spawn(),
-                })
+    pub async fn new<C: Into<VaccelConfig>>(config: C) -> Result<Self> {
+        let config = config.into();
+        let (client, server) = connect(&config).await?;
+
+        Ok(Self {
+            timeout: config.timeout,
+            cancellation: None,
+            inner: Arc::new(Inner {
+                config,
+                connection: RwLock::new(Connection {
+                    generation: 0,
+                    client,
+                    server,
+                }),
+                reconnecting: Mutex::new(()),
+                session_id: AtomicU64::new(1),
+                sessions: DashMap::new(),
+                resource_id: AtomicU64::new(1),
+                resources: DashMap::new(),
+            }),
+        })
+    }
+
+    /// Returns a handle to the same client whose requests use `timeout`
+    /// instead of the configured default
+    pub fn with_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            timeout,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a handle to the same client whose requests fail with
+    /// `Error::Cancelled` once `token` is cancelled. Requests in flight at
+    /// that point are cancelled on the agent as well.
+    pub fn with_cancellation(&self, token: CancellationToken) -> Self {
+        Self {
+            cancellation: Some(token),
+            ..self.clone()
+        }
+    }
+
+    fn context(&self) -> Context {
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + self.timeout;
+        ctx
+    }
+
+    /// How long to wait for in a single request, in slices that fit in the
+    /// request deadline
+    fn wait_slice(&self) -> Duration {
+        (self.timeout / 2).max(MIN_WAIT_SLICE)
+    }
+
+    /// Optional protocol features supported by the agent
+    pub fn server_features(&self) -> Vec<String> {
+        self.inner
+            .connection
+            .read()
+            .unwrap()
+            .server
+            .features
+            .clone()
+    }
+
+    /// Fail with `Error::Unsupported` unless the agent supports `feature`
+    fn require(&self, feature: &str) -> Result<()> {
+        let connection = self.inner.connection.read().unwrap();
+        if connection.server.features.iter().any(|f| f == feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported(feature.to_string()))
+        }
+    }
+
+    fn connection(&self) -> (u64, VaccelAPIClient) {
+        let connection = self.inner.connection.read().unwrap();
+        (connection.generation, connection.client.clone())
+    }
+
+    /// Issue an idempotent RPC through `f`, reconnecting and retrying once if
+    /// the connection to the agent has been lost in the meantime.
+    async fn call<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: Fn(VaccelAPIClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.cancellable(self.call_with_reconnect(f, true)).await
+    }
+
+    /// Issue an RPC through `f` that must not run twice. If the connection to
+    /// the agent has been lost, it is re-established for the requests that
+    /// follow, but the RPC fails with `Error::Disconnected`: the agent may
+    /// or may not have run it.
+    async fn call_once<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: Fn(VaccelAPIClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        self.cancellable(self.call_with_reconnect(f, false)).await
+    }
+
+    async fn cancellable<T>(&self, call: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.cancellation {
+            None => call.await,
+            Some(ref token) => {
+                // Dropping the request future makes tarpc cancel the request
+                // on the server side as well
+                tokio::select! {
+                    res = call => res,
+                    _ = token.cancelled() => Err(Error::Cancelled),
+                }
             }
-            VaccelConfig::Vsock(cid, port) => {
-                let stream = VsockStream::connect(cid, port).await?;
-                let transport = serde_transport::new(
-                    Framed::new(stream, LengthDelimitedCodec::new()),
-                    Json::default(),
-                );
+        }
+    }
 
-                Ok(Self {
-                    inner: VaccelAPIClient::new(client::Config::default(), transport).spawn(),
-                })
+    async fn call_with_reconnect<T, F, Fut>(&self, f: F, retry: bool) -> Result<T>
+    where
+        F: Fn(VaccelAPIClient) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let (generation, client) = self.connection();
+        match f(client).await {
+            Err(Error::Disconnected) if self.inner.config.reconnect.is_some() => {
+                self.reconnect(generation).await?;
+                if !retry {
+                    return Err(Error::Disconnected);
+                }
+                let (_, client) = self.connection();
+                f(client).await
             }
-            VaccelConfig::Unix(path) => {
-                let stream = UnixStream::connect(path).await?;
-                let transport = serde_transport::new(
-                    Framed::new(stream, LengthDelimitedCodec::new()),
-                    Json::default(),
-                );
+            res => res,
+        }
+    }
 
-                Ok(Self {
-                    inner: VaccelAPIClient::new(client::Config::default(), transport).spawn(),
-                })
+    async fn reconnect(&self, generation: u64) -> Result<()> {
+        let _guard = self.inner.reconnecting.lock().await;
+
+        // Somebody else already replaced the connection we saw failing
+        if self.inner.connection.read().unwrap().generation != generation {
+            return Ok(());
+        }
+
+        let policy = self
+            .inner
+            .config
+            .reconnect
+            .as_ref()
+            .ok_or(Error::Disconnected)?;
+
+        let mut backoff = policy.initial_backoff;
+        let mut attempt = 1;
+        let (client, server) = loop {
+            match connect(&self.inner.config).await {
+                Ok(connection) => break connection,
+                Err(e) if attempt >= policy.max_attempts => {
+                    warn!("Giving up reconnecting after {} attempts: {}", attempt, e);
+                    return Err(e);
+                }
+                Err(e) => {
+                    debug!("Reconnect attempt {} failed: {}", attempt, e);
+                    tokio::time::sleep(backoff).await;
+                    backoff = cmp::min(backoff * 2, policy.max_backoff);
+                    attempt += 1;
+                }
             }
+        };
+
+        // Collect the ids first, so that we do not hold map locks across
+        // the replayed RPCs
+        let sessions: Vec<u64> = self.inner.sessions.iter().map(|e| *e.key()).collect();
+        for id in sessions {
+            let remote = if policy.replay {
+                match client.new_session(self.context()).await {
+                    Ok(Ok(remote)) => Some(remote),
+                    _ => None,
+                }
+            } else {
+                None
+            };
+
+            if remote.is_none() {
+                warn!("Could not re-establish session {}", id);
+            }
+            self.inner.sessions.insert(id, remote);
+        }
+
+        let resources: Vec<(u64, Option<Resource>)> = self
+            .inner
+            .resources
+            .iter()
+            .map(|e| (*e.key(), e.value().resource.clone()))
+            .collect();
+        for (id, resource) in resources {
+            let remote = match resource {
+                Some(resource) => match client.register_resource(self.context(), resource).await {
+                    Ok(Ok(remote)) => Some(remote),
+                    _ => None,
+                },
+                None => None,
+            };
+
+            if remote.is_none() {
+                warn!("Could not re-register resource {}", id);
+            }
+            if let Some(mut entry) = self.inner.resources.get_mut(&id) {
+                entry.remote = remote;
+            }
+        }
+
+        let mut connection = self.inner.connection.write().unwrap();
+        connection.generation += 1;
+        connection.client = client;
+        connection.server = server;
+
+        Ok(())
+    }
+
+    /// Id of a session on the current connection
+    fn remote_session(&self, id: u64) -> Result<u64> {
+        match self.inner.sessions.get(&id).map(|e| *e.value()) {
+            None => Err(Error::InvalidArgument),
+            Some(None) => Err(Error::SessionLost(id)),
+            Some(Some(remote)) => Ok(remote),
         }
     }
 
+    /// Id of a resource on the current connection
+    fn remote_resource(&self, id: u64) -> Result<u64> {
+        match self.inner.resources.get(&id).map(|e| e.value().remote) {
+            None => Err(Error::InvalidArgument),
+            Some(None) => Err(Error::ResourceLost(id)),
+            Some(Some(remote)) => Ok(remote),
+        }
+    }
+
+    /// Track a session created on the agent, returning its handle id
+    fn add_session(&self, remote: u64) -> u64 {
+        let id = self.inner.session_id.fetch_add(1, Ordering::SeqCst);
+        self.inner.sessions.insert(id, Some(remote));
+        id
+    }
+
+    /// Track a resource registered with the agent, returning its handle id
+    fn add_resource(&self, resource: Resource, remote: u64) -> u64 {
+        let replay = match self.inner.config.reconnect {
+            Some(ref policy) if policy.replay => Some(resource),
+            _ => None,
+        };
+
+        let id = self.inner.resource_id.fetch_add(1, Ordering::SeqCst);
+        self.inner.resources.insert(
+            id,
+            RegisteredResource {
+                resource: replay,
+                remote: Some(remote),
+            },
+        );
+        id
+    }
+
+    /// Translate the handle ids `operation` refers to into ids on the
+    /// current connection
+    fn remote_operation(&self, operation: Operation) -> Result<Operation> {
+        let remote_session = |session| match session {
+            Ref::Id(id) => self.remote_session(id).map(Ref::Id),
+            output => Ok(output),
+        };
+        let remote_resource = |resource| match resource {
+            Ref::Id(id) => self.remote_resource(id).map(Ref::Id),
+            output => Ok(output),
+        };
+
+        Ok(match operation {
+            Operation::DestroySession(session) => {
+                Operation::DestroySession(remote_session(session)?)
+            }
+            Operation::TfSessionLoad(model) => Operation::TfSessionLoad(remote_resource(model)?),
+            Operation::TfSessionRun {
+                model,
+                inputs,
+                outputs,
+            } => Operation::TfSessionRun {
+                model: remote_resource(model)?,
+                inputs,
+                outputs,
+            },
+            Operation::TfSessionUnload(model) => {
+                Operation::TfSessionUnload(remote_resource(model)?)
+            }
+            operation => operation,
+        })
+    }
+
     pub async fn new_session(&self) -> Result<Session> {
-        let id = self.inner.new_session(context::current()).await??;
-        Ok(Session::new().with_id(id))
+        let remote = self
+            .call_once(|client| async move {
+                client
+                    .new_session(self.context())
+                    .await
+                    .map_err(transport)?
+            })
+            .await?;
+
+        Ok(Session::new().with_id(self.add_session(remote)))
     }
 
     pub async fn destroy_session(&self, session: &Session) -> Result<()> {
-        self.inner
-            .destroy_session(context::current(), session.id())
-            .await?
+        self.call(|client| async move {
+            let id = self.remote_session(session.id())?;
+            client
+                .destroy_session(self.context(), id)
+                .await
+                .map_err(transport)?
+        })
+        .await?;
+
+        self.inner.sessions.remove(&session.id());
+        Ok(())
+    }
+
+    pub async fn register_resource(&self, resource: Resource) -> Result<u64> {
+        let remote = self
+            .call_once(|client| {
+                let resource = resource.clone();
+                async move {
+                    client
+                        .register_resource(self.context(), resource)
+                        .await
+                        .map_err(transport)?
+                }
+            })
+            .await?;
+
+        Ok(self.add_resource(resource, remote))
+    }
+
+    /// Release a resource registered with `register_resource`
+    pub async fn unregister_resource(&self, id: u64) -> Result<()> {
+        self.require(FEATURE_UNREGISTER_RESOURCE)?;
+        self.call(|client| async move {
+            let remote = self.remote_resource(id)?;
+            client
+                .unregister_resource(self.context(), remote)
+                .await
+                .map_err(transport)?
+        })
+        .await?;
+
+        self.inner.resources.remove(&id);
+        Ok(())
     }
 
     pub async fn tf_session_load(&self, model_id: u64) -> Result<()> {
+        self.call(|client| async move {
+            let id = self.remote_resource(model_id)?;
+            client
+                .tf_session_load(self.context(), id)
+                .await
+                .map_err(transport)?
+        })
+        .await
+    }
+
+    pub async fn tf_session_unload(&self, model_id: u64) -> Result<()> {
+        self.call(|client| async move {
+            let id = self.remote_resource(model_id)?;
+            client
+                .tf_session_unload(self.context(), id)
+                .await
+                .map_err(transport)?
+        })
+        .await
+    }
+
+    pub async fn tf_session_run(
+        &self,
+        model_id: u64,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    ) -> Result<Vec<Tensor>> {
+        self.require(FEATURE_TF_SESSION_RUN)?;
+        self.call_once(|client| {
+            let inputs = inputs.clone();
+            let outputs = outputs.clone();
+            async move {
+                let id = self.remote_resource(model_id)?;
+                client
+                    .tf_session_run(self.context(), id, inputs, outputs)
+                    .await
+                    .map_err(transport)?
+            }
+        })
+        .await
+    }
+
+    /// Plugins loaded by the agent
+    pub async fn plugins(&self) -> Result<Vec<Plugin>> {
+        self.require(FEATURE_PLUGINS)?;
+        self.call(|client| async move { client.plugins(self.context()).await.map_err(transport)? })
+            .await
+    }
+
+    /// Start building a batch of operations, executed by the agent in a
+    /// single request
+    pub fn batch(&self) -> Batch<'_> {
+        Batch {
+            client: self,
+            operations: Vec::new(),
+            stop_on_error: false,
+        }
+    }
+
+    /// Execute `operations` on the agent in a single request, returning the
+    /// result of each one. Operations refer to sessions and resources by
+    /// their handle ids, and so do the returned outputs.
+    pub async fn submit_batch(
+        &self,
+        operations: Vec<Operation>,
+        stop_on_error: bool,
+    ) -> Result<Vec<Result<Output>>> {
+        self.require(FEATURE_BATCH)?;
+        let (results, errors) = self
+            .call_once(|client| {
+                let operations = operations.clone();
+                async move {
+                    // An operation referring to a lost session or resource
+                    // fails on its own rather than failing the whole batch
+                    let mut errors = vec![None; operations.len()];
+                    let operations = operations
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, operation)| {
+                            self.remote_operation(operation.clone())
+                                .unwrap_or_else(|e| {
+                                    errors[index] = Some(e);
+                                    operation.unresolvable(index)
+                                })
+                        })
+                        .collect();
+                    let results = client
+                        .batch(self.context(), operations, stop_on_error)
+                        .await
+                        .map_err(transport)??;
+                    Ok((results, errors))
+                }
+            })
+            .await?;
+
+        let mut outputs: Vec<Result<Output>> = Vec::with_capacity(results.len());
+        for ((operation, res), error) in operations.into_iter().zip(results).zip(errors) {
+            let res = match error {
+                Some(e) => Err(e),
+                None => self.local_output(operation, res, &outputs),
+            };
+            outputs.push(res);
+        }
+
+        Ok(outputs)
+    }
+
+    /// Track what `operation` created on the agent and forget what it
+    /// destroyed, so that its output refers to handle ids like the rest of
+    /// the API. `outputs` holds the translated outputs of the operations
+    /// preceding it in a batch.
+    fn local_output(
+        &self,
+        operation: Operation,
+        res: Result<Output>,
+        outputs: &[Result<Output>],
+    ) -> Result<Output> {
+        match (operation, res) {
+            (Operation::NewSession, Ok(Output::Session(remote))) => {
+                Ok(Output::Session(self.add_session(remote)))
+            }
+            (Operation::RegisterResource(resource), Ok(Output::Resource(remote))) => {
+                Ok(Output::Resource(self.add_resource(resource, remote)))
+            }
+            (Operation::DestroySession(session), Ok(output)) => {
+                if let Ok(id) = session.session(outputs) {
+                    self.inner.sessions.remove(&id);
+                }
+                Ok(output)
+            }
+            (_, res) => res,
+        }
+    }
+
+    /// Run `operation` in the background on the agent, on behalf of
+    /// `session`. The job is cancelled if the session is destroyed.
+    pub async fn submit_job(&self, session: &Session, operation: Operation) -> Result<JobHandle> {
+        self.require(FEATURE_JOBS)?;
+        let id = self
+            .call_once(|client| {
+                let operation = operation.clone();
+                async move {
+                    let session = self.remote_session(session.id())?;
+                    let operation = self.remote_operation(operation)?;
+                    client
+                        .submit_job(self.context(), session, operation)
+                        .await
+                        .map_err(transport)?
+                }
+            })
+            .await?;
+
+        Ok(JobHandle {
+            client: self.clone(),
+            id,
+            operation: Some(operation),
+            status: JobStatus::Running,
+        })
+    }
+}
+
+impl Vaccel {
+    /// Handle id of a session on the current connection
+    fn local_session(&self, remote: u64) -> Option<u64> {
         self.inner
-            .tf_session_load(context::current(), model_id)
-            .await?
+            .sessions
+            .iter()
+            .find(|e| *e.value() == Some(remote))
+            .map(|e| *e.key())
+    }
+
+    /// Translate an event of the agent to refer to handle ids, dropping
+    /// events about sessions we do not know of
+    fn local_event(&self, event: Event) -> Option<Event> {
+        match event {
+            Event::SessionReaped { session } => {
+                let id = self.local_session(session)?;
+                self.inner.sessions.insert(id, None);
+                Some(Event::SessionReaped { session: id })
+            }
+            Event::JobFinished { session, job } => self
+                .local_session(session)
+                .map(|id| Event::JobFinished { session: id, job }),
+            event => Some(event),
+        }
+    }
+
+    async fn next_events(&self, timeout: Duration) -> Result<Vec<Event>> {
+        self.require(FEATURE_EVENTS)?;
+        let events = self
+            .call(|client| async move {
+                client
+                    .next_events(self.context(), timeout)
+                    .await
+                    .map_err(transport)?
+            })
+            .await?;
+
+        Ok(events
+            .into_iter()
+            .filter_map(|event| self.local_event(event))
+            .collect())
+    }
+
+    /// Subscribe to the events the agent pushes to this client. The stream
+    /// ends after yielding the first error.
+    ///
+    /// Sessions reaped by the agent are lost, as if they could not be
+    /// re-established after a reconnect.
+    pub async fn events(&self) -> Result<impl Stream<Item = Result<Event>> + Send> {
+        // Subscribe right away, so that no event is missed from now on
+        let pending: VecDeque<Event> = self.next_events(Duration::from_secs(0)).await?.into();
+        let client = self.clone();
+
+        Ok(stream::unfold(
+            Some((client, pending)),
+            |state| async move {
+                let (client, mut pending) = state?;
+                loop {
+                    if let Some(event) = pending.pop_front() {
+                        return Some((Ok(event), Some((client, pending))));
+                    }
+
+                    match client.next_events(client.wait_slice()).await {
+                        Ok(events) => pending.extend(events),
+                        Err(e) => return Some((Err(e), None)),
+                    }
+                }
+            },
+        ))
+    }
+}
+
+/// A job running on the agent. Awaiting the handle waits for the job to
+/// complete and yields its output.
+#[derive(Debug)]
+pub struct JobHandle {
+    client: Vaccel,
+    id: u64,
+    /// The operation of the job, until its output has been translated
+    operation: Option<Operation>,
+    /// Last status reported by the agent
+    status: JobStatus,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Record `status`, reported by the agent for this job
+    fn update(&mut self, status: JobStatus) -> JobStatus {
+        let status = match (status, self.operation.take()) {
+            (JobStatus::Finished(res), Some(operation)) => {
+                JobStatus::Finished(self.client.local_output(operation, res, &[]))
+            }
+            (status, operation) => {
+                self.operation = operation;
+                status
+            }
+        };
+
+        self.status = status.clone();
+        status
+    }
+
+    /// Current status of the job
+    pub async fn poll(&mut self) -> Result<JobStatus> {
+        if !self.status.is_running() {
+            return Ok(self.status.clone());
+        }
+
+        let (vaccel, id) = (&self.client, self.id);
+        let status = vaccel
+            .call(|client| async move {
+                client
+                    .poll_job(vaccel.context(), id)
+                    .await
+                    .map_err(transport)?
+            })
+            .await?;
+        Ok(self.update(status))
+    }
+
+    /// Wait for the job to complete and return its output
+    pub async fn wait(mut self) -> Result<Output> {
+        let slice = self.client.wait_slice();
+
+        while self.status.is_running() {
+            let (vaccel, id) = (&self.client, self.id);
+            let status = vaccel
+                .call(|client| async move {
+                    client
+                        .wait_job(vaccel.context(), id, slice)
+                        .await
+                        .map_err(transport)?
+                })
+                .await?;
+            self.update(status);
+        }
+
+        match self.status {
+            JobStatus::Finished(res) => res,
+            _ => Err(Error::Cancelled),
+        }
+    }
+
+    /// Cancel the job, if still running. A job already executing in a plugin
+    /// runs to completion, but its output is discarded.
+    pub async fn cancel(self) -> Result<()> {
+        // The agent forgets jobs once it has reported them done
+        if !self.status.is_running() {
+            return Ok(());
+        }
+
+        let (vaccel, id) = (&self.client, self.id);
+        vaccel
+            .call(|client| async move {
+                client
+                    .cancel_job(vaccel.context(), id)
+                    .await
+                    .map_err(transport)?
+            })
+            .await
+    }
+}
+
+impl IntoFuture for JobHandle {
+    type Output = Result<Output>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.wait())
+    }
+}
+
+/// Builds a batch of operations executed by the agent in a single request.
+/// Operations may refer to the output of earlier ones with `Ref::Output`.
+pub struct Batch<'a> {
+    client: &'a Vaccel,
+    operations: Vec<Operation>,
+    stop_on_error: bool,
+}
+
+impl Batch<'_> {
+    pub fn new_session(mut self) -> Self {
+        self.operations.push(Operation::NewSession);
+        self
+    }
+
+    pub fn destroy_session<R: Into<Ref>>(mut self, session: R) -> Self {
+        self.operations
+            .push(Operation::DestroySession(session.into()));
+        self
+    }
+
+    pub fn register_resource(mut self, resource: Resource) -> Self {
+        self.operations.push(Operation::RegisterResource(resource));
+        self
+    }
+
+    pub fn tf_session_load<R: Into<Ref>>(mut self, model: R) -> Self {
+        self.operations.push(Operation::TfSessionLoad(model.into()));
+        self
+    }
+
+    pub fn tf_session_run<R: Into<Ref>>(
+        mut self,
+        model: R,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    ) -> Self {
+        self.operations.push(Operation::TfSessionRun {
+            model: model.into(),
+            inputs,
+            outputs,
+        });
+        self
+    }
+
+    pub fn tf_session_unload<R: Into<Ref>>(mut self, model: R) -> Self {
+        self.operations
+            .push(Operation::TfSessionUnload(model.into()));
+        self
+    }
+
+    /// Skip the remaining operations once one fails
+    pub fn stop_on_error(mut self, stop: bool) -> Self {
+        self.stop_on_error = stop;
+        self
+    }
+
+    pub async fn submit(self) -> Result<Vec<Result<Output>>> {
+        self.client
+            .submit_batch(self.operations, self.stop_on_error)
+            .await
     }
 }
 
@@ -88,9 +1046,60 @@ impl Vaccel {
 mod test {
     use super::*;
 
+    use futures::StreamExt;
+    use tarpc::server::{BaseChannel, Channel};
+    use tokio::net::UnixListener;
+
+    use crate::server::VaccelAPI;
+
+    use crate::tensorflow::models::TensorflowSavedModelBuilder;
+    use crate::tensorflow::DataType;
+
+    /// Serve a single connection accepted from `listener`, until the
+    /// returned task is aborted
+    fn serve_one(listener: Arc<UnixListener>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let transport = serde_transport::new(
+                Framed::new(stream, LengthDelimitedCodec::new()),
+                Json::default(),
+            );
+            BaseChannel::with_defaults(transport)
+                .execute(Server::new().unwrap().connection(Peer::Local).serve())
+                .await;
+        })
+    }
+
+    async fn reconnecting_client(replay: bool) -> (Vaccel, Arc<UnixListener>, mktemp::Temp) {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        let path = dir.as_path().join("vaccel.sock");
+        let listener = Arc::new(UnixListener::bind(&path).unwrap());
+
+        let agent = serve_one(listener.clone());
+        let policy = ReconnectPolicy {
+            replay,
+            ..Default::default()
+        };
+        let client = Vaccel::new(VaccelConfig::new(Endpoint::Unix(path)).reconnect(policy))
+            .await
+            .expect("Could not create client");
+
+        let session = client
+            .new_session()
+            .await
+            .expect("Could not create session");
+        assert_eq!(session.id(), 1);
+
+        // Drop the connection from the agent side
+        agent.abort();
+        let _ = agent.await;
+
+        (client, listener, dir)
+    }
+
     #[tokio::test]
     async fn basic_client_session() {
-        let client = Vaccel::new(VaccelConfig::Local)
+        let client = Vaccel::new(Endpoint::Local)
             .await
             .expect("Could not create Server");
 
@@ -100,5 +1109,265 @@ mod test {
             .expect("Could not create session");
 
         assert_eq!(session.id(), 1);
+
+        let plugins = client.plugins().await.expect("Could not list plugins");
+        assert_eq!(plugins[0].name, "vaccel-noop");
+
+        // Features the agent does not advertise are not used
+        assert!(client
+            .server_features()
+            .contains(&FEATURE_PLUGINS.to_string()));
+        client
+            .inner
+            .connection
+            .write()
+            .unwrap()
+            .server
+            .features
+            .clear();
+        match client.plugins().await {
+            Err(Error::Unsupported(feature)) => assert_eq!(feature, FEATURE_PLUGINS),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_and_cancellation() {
+        let client = Vaccel::new(Endpoint::Local)
+            .await
+            .expect("Could not create Server");
+
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let model_id = client
+            .register_resource(Resource::TensorflowSavedModel(model))
+            .await
+            .expect("Could not register resource");
+
+        match client
+            .with_timeout(Duration::from_secs(0))
+            .tf_session_load(model_id)
+            .await
+        {
+            Err(Error::DeadlineExceeded) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let token = CancellationToken::new();
+        token.cancel();
+        match client.with_cancellation(token).new_session().await {
+            Err(Error::Cancelled) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn batched_operations() {
+        let client = Vaccel::new(Endpoint::Local)
+            .await
+            .expect("Could not create Server");
+
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let input = Tensor {
+            dims: vec![2],
+            data_type: DataType::Float,
+            data: vec![0; 8],
+        };
+        let node = |name: &str| Node {
+            name: name.to_string(),
+            id: 0,
+        };
+
+        let results = client
+            .batch()
+            .new_session()
+            .register_resource(Resource::TensorflowSavedModel(model))
+            .tf_session_load(Ref::Output(1))
+            .tf_session_run(
+                Ref::Output(1),
+                vec![(node("input"), input.clone())],
+                vec![node("output")],
+            )
+            .tf_session_unload(Ref::Output(1))
+            .destroy_session(Ref::Output(0))
+            .submit()
+            .await
+            .expect("Could not submit batch");
+        let results: Vec<Output> = results
+            .into_iter()
+            .collect::<Result<_>>()
+            .expect("Batched operation failed");
+        assert_eq!(
+            results,
+            vec![
+                Output::Session(1),
+                Output::Resource(1),
+                Output::Done,
+                Output::Tensors(vec![input]),
+                Output::Done,
+                Output::Done,
+            ]
+        );
+
+        // The model is no longer loaded, so running it fails
+        let results = client
+            .batch()
+            .tf_session_run(1, Vec::new(), Vec::new())
+            .new_session()
+            .stop_on_error(true)
+            .submit()
+            .await
+            .expect("Could not submit batch");
+        assert_eq!(results.len(), 1);
+        assert!(results[0].is_err());
+    }
+
+    #[tokio::test]
+    async fn background_jobs() {
+        let client = Vaccel::new(Endpoint::Local)
+            .await
+            .expect("Could not create Server");
+        let session = client
+            .new_session()
+            .await
+            .expect("Could not create session");
+
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let job = client
+            .submit_job(
+                &session,
+                Operation::RegisterResource(Resource::TensorflowSavedModel(model)),
+            )
+            .await
+            .expect("Could not submit job");
+        let model_id = match job.await {
+            Ok(Output::Resource(id)) => id,
+            res => panic!("Unexpected result: {:?}", res),
+        };
+
+        let mut job = client
+            .submit_job(&session, Operation::TfSessionLoad(Ref::Id(model_id)))
+            .await
+            .expect("Could not submit job");
+        while job.poll().await.expect("Could not poll job").is_running() {
+            tokio::task::yield_now().await;
+        }
+        match job.poll().await {
+            Ok(JobStatus::Finished(Ok(Output::Done))) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // The agent forgets jobs once it has reported them done
+        let (_, agent) = client.connection();
+        match agent.poll_job(client.context(), job.id()).await.unwrap() {
+            Err(Error::InvalidArgument) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        job.cancel().await.expect("Could not cancel finished job");
+
+        // Jobs of destroyed sessions are gone
+        let job = client
+            .submit_job(&session, Operation::TfSessionUnload(Ref::Id(model_id)))
+            .await
+            .expect("Could not submit job");
+        client
+            .destroy_session(&session)
+            .await
+            .expect("Could not destroy session");
+        match job.wait().await {
+            Err(Error::InvalidArgument) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn job_events() {
+        let client = Vaccel::new(Endpoint::Local)
+            .await
+            .expect("Could not create Server");
+        let mut events = Box::pin(client.events().await.expect("Could not subscribe"));
+
+        let session = client
+            .new_session()
+            .await
+            .expect("Could not create session");
+        let job = client
+            .submit_job(&session, Operation::NewSession)
+            .await
+            .expect("Could not submit job");
+
+        match events.next().await {
+            Some(Ok(Event::JobFinished {
+                session: 1,
+                job: id,
+            })) => assert_eq!(id, job.id()),
+            res => panic!("Unexpected event: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnect_replays_sessions() {
+        let (client, listener, _dir) = reconnecting_client(true).await;
+        let _agent = serve_one(listener);
+
+        let session = Session::new().with_id(1);
+        client
+            .destroy_session(&session)
+            .await
+            .expect("Session was not replayed");
+    }
+
+    #[tokio::test]
+    async fn reconnect_does_not_retry_non_idempotent_requests() {
+        let (client, listener, _dir) = reconnecting_client(true).await;
+        let _agent = serve_one(listener);
+
+        // The agent may have created the session before the connection
+        // dropped, so the request is not sent again
+        match client.new_session().await {
+            Err(Error::Disconnected) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // but the connection is back for the requests that follow
+        let session = client
+            .new_session()
+            .await
+            .expect("Could not create session");
+        assert_eq!(session.id(), 2);
+    }
+
+    #[tokio::test]
+    async fn reconnect_without_replay_loses_sessions() {
+        let (client, listener, _dir) = reconnecting_client(false).await;
+        let _agent = serve_one(listener);
+
+        let session = Session::new().with_id(1);
+        match client.destroy_session(&session).await {
+            Err(Error::SessionLost(1)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Only the operations referring to the lost session fail
+        let results = client
+            .batch()
+            .destroy_session(1)
+            .new_session()
+            .submit()
+            .await
+            .expect("Could not submit batch");
+        match results[0] {
+            Err(Error::SessionLost(1)) => {}
+            ref res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(matches!(results[1], Ok(Output::Session(_))));
     }
 }
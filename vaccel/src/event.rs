@@ -0,0 +1,17 @@
+//! Events pushed by the agent to its clients
+//!
+//! Clients subscribe by long-polling `VaccelAPI::next_events`. Events about
+//! sessions are only delivered to the client that owns the session.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    /// The agent destroyed a session of the client
+    SessionReaped { session: u64 },
+    /// A plugin was reloaded. Models loaded through it need to be loaded
+    /// again.
+    PluginReloaded { name: String },
+    /// A job of the client completed
+    JobFinished { session: u64, job: u64 },
+}
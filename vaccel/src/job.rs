@@ -0,0 +1,27 @@
+//! Jobs: operations the agent runs in the background
+//!
+//! Operations such as model loads can take far longer than any reasonable
+//! request deadline. Submitted as jobs, they run on the agent on behalf of a
+//! session, while the client polls for, or waits on, their completion.
+
+use serde::{Deserialize, Serialize};
+
+use crate::batch::Output;
+use crate::Result;
+
+/// State of a job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JobStatus {
+    /// The job is still running
+    Running,
+    /// The job completed, successfully or not
+    Finished(Result<Output>),
+    /// The job was cancelled before completing
+    Cancelled,
+}
+
+impl JobStatus {
+    pub fn is_running(&self) -> bool {
+        matches!(self, JobStatus::Running)
+    }
+}
@@ -1,14 +1,26 @@
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
+use crate::quota::QuotaKind;
+
+pub mod admin;
+pub mod auth;
+pub mod batch;
+pub mod blocking;
 pub mod client;
+pub mod event;
+pub mod job;
+pub mod metrics;
 mod plugin;
+pub mod quota;
 pub mod resource;
+pub mod rundir;
 pub mod server;
 pub mod session;
 pub mod tensorflow;
+pub mod tls;
 
-#[derive(Debug, Deserialize, Serialize, Error)]
+#[derive(Debug, Clone, Deserialize, Serialize, Error)]
 pub enum Error {
     /// An invalid argument was passed by the user
     #[error("Invalid argument")]
@@ -19,6 +31,46 @@ pub enum Error {
     /// Error while loading a plugin
     #[error("Plugin loading error")]
     Plugin(String),
+    /// Error while setting up or performing TLS
+    #[error("TLS error")]
+    Tls(String),
+    /// The client has not authenticated with the agent, or presented an
+    /// unknown token
+    #[error("Authentication required")]
+    Unauthenticated,
+    /// The authenticated tenant is not allowed to perform the operation
+    #[error("Permission denied")]
+    PermissionDenied,
+    /// Client and agent speak incompatible protocol versions
+    #[error("Incompatible protocol version")]
+    Incompatible { client: u32, server: u32 },
+    /// The connection to the vAccel agent was lost
+    #[error("Connection to vAccel agent lost")]
+    Disconnected,
+    /// A session could not be re-established after reconnecting
+    #[error("Session lost")]
+    SessionLost(u64),
+    /// A resource could not be re-registered after reconnecting
+    #[error("Resource lost")]
+    ResourceLost(u64),
+    /// The request did not complete before its deadline
+    #[error("Deadline exceeded")]
+    DeadlineExceeded,
+    /// The request was cancelled by the caller
+    #[error("Request cancelled")]
+    Cancelled,
+    /// The agent is at its connection or request limit
+    #[error("Agent busy")]
+    Busy,
+    /// The request would take the tenant over one of its quotas
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(QuotaKind),
+    /// The agent does not support the requested protocol feature
+    #[error("Not supported by the agent: {0}")]
+    Unsupported(String),
+    /// A token file could not be parsed
+    #[error("Invalid token file: {0}")]
+    InvalidTokenFile(String),
     /// Undefined error
     #[error("BUG: Undefined error")]
     UndefinedError,
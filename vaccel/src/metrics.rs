@@ -0,0 +1,195 @@
+//! Prometheus metrics of the vAccel agent
+//!
+//! The server counts every RPC it answers and every call it dispatches to a
+//! plugin, along with their latency and the kind of error they failed with.
+//! Gauges describing the state of the server are refreshed when the metrics
+//! are gathered, through `Server::metrics`.
+
+use std::time::Duration;
+
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use vaccel_plugins::{InvocationError, VaccelPluginFunctions};
+
+use crate::Error;
+
+pub struct Metrics {
+    registry: Registry,
+    rpc_calls: IntCounterVec,
+    rpc_errors: IntCounterVec,
+    rpc_duration: HistogramVec,
+    plugin_calls: IntCounterVec,
+    plugin_errors: IntCounterVec,
+    plugin_duration: HistogramVec,
+    pub(crate) sessions: IntGauge,
+    pub(crate) resource_bytes: IntGauge,
+    pub(crate) connections: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let counter = |name: &str, help: &str, labels: &[&str]| {
+            IntCounterVec::new(Opts::new(name, help), labels).unwrap()
+        };
+        let histogram = |name: &str, help: &str, labels: &[&str]| {
+            HistogramVec::new(HistogramOpts::new(name, help), labels).unwrap()
+        };
+        let gauge = |name: &str, help: &str| IntGauge::new(name, help).unwrap();
+
+        let metrics = Metrics {
+            registry: Registry::new(),
+            rpc_calls: counter(
+                "vaccel_rpc_calls_total",
+                "RPC requests answered",
+                &["method"],
+            ),
+            rpc_errors: counter(
+                "vaccel_rpc_errors_total",
+                "RPC requests that failed",
+                &["method", "kind"],
+            ),
+            rpc_duration: histogram(
+                "vaccel_rpc_duration_seconds",
+                "Time taken to answer RPC requests",
+                &["method"],
+            ),
+            plugin_calls: counter(
+                "vaccel_plugin_calls_total",
+                "Calls dispatched to plugins",
+                &["plugin", "function"],
+            ),
+            plugin_errors: counter(
+                "vaccel_plugin_errors_total",
+                "Plugin calls that failed",
+                &["plugin", "function", "kind"],
+            ),
+            plugin_duration: histogram(
+                "vaccel_plugin_duration_seconds",
+                "Time taken by plugin calls",
+                &["plugin", "function"],
+            ),
+            sessions: gauge("vaccel_sessions", "Active sessions"),
+            resource_bytes: gauge(
+                "vaccel_resource_bytes",
+                "Bytes held by registered resources",
+            ),
+            connections: gauge("vaccel_connections", "Open client connections"),
+        };
+
+        let registry = &metrics.registry;
+        registry
+            .register(Box::new(metrics.rpc_calls.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.rpc_errors.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.rpc_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.plugin_calls.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.plugin_errors.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.plugin_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.sessions.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.resource_bytes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(metrics.connections.clone()))
+            .unwrap();
+
+        metrics
+    }
+
+    /// Registry holding the metrics, for registering more of them
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// The metrics, in the Prometheus text format
+    pub fn encode(&self) -> String {
+        let mut buf = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buf)
+            .expect("Could not encode metrics");
+
+        String::from_utf8(buf).expect("Metrics are not UTF-8")
+    }
+
+    pub(crate) fn observe_rpc(&self, method: &str, duration: Duration, error: Option<&Error>) {
+        self.rpc_calls.with_label_values(&[method]).inc();
+        self.rpc_duration
+            .with_label_values(&[method])
+            .observe(duration.as_secs_f64());
+        if let Some(e) = error {
+            self.rpc_errors
+                .with_label_values(&[method, error_kind(e)])
+                .inc();
+        }
+    }
+
+    pub(crate) fn observe_plugin(
+        &self,
+        plugin: &str,
+        function: VaccelPluginFunctions,
+        duration: Duration,
+        error: Option<&InvocationError>,
+    ) {
+        let function = format!("{:?}", function);
+        self.plugin_calls
+            .with_label_values(&[plugin, &function])
+            .inc();
+        self.plugin_duration
+            .with_label_values(&[plugin, &function])
+            .observe(duration.as_secs_f64());
+        if let Some(e) = error {
+            let kind = match e {
+                InvocationError::InvalidArgument(_) => "invalid_argument",
+                InvocationError::NotImplemented => "not_implemented",
+                InvocationError::Implementation { .. } => "implementation",
+                InvocationError::Unknown(_) => "unknown",
+            };
+            self.plugin_errors
+                .with_label_values(&[plugin, &function, kind])
+                .inc();
+        }
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics::new()
+    }
+}
+
+/// Label of the `kind` of an error
+fn error_kind(error: &Error) -> &'static str {
+    match error {
+        Error::InvalidArgument => "invalid_argument",
+        Error::IOError(_) => "io",
+        Error::Plugin(_) => "plugin",
+        Error::Tls(_) => "tls",
+        Error::Unauthenticated => "unauthenticated",
+        Error::PermissionDenied => "permission_denied",
+        Error::Incompatible { .. } => "incompatible",
+        Error::Disconnected => "disconnected",
+        Error::SessionLost(_) => "session_lost",
+        Error::ResourceLost(_) => "resource_lost",
+        Error::DeadlineExceeded => "deadline_exceeded",
+        Error::Cancelled => "cancelled",
+        Error::Busy => "busy",
+        Error::QuotaExceeded(_) => "quota_exceeded",
+        Error::Unsupported(_) => "unsupported",
+        Error::InvalidTokenFile(_) => "invalid_token_file",
+        Error::UndefinedError => "undefined",
+    }
+}
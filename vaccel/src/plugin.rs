@@ -1,13 +1,19 @@
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 
-use log::{debug, error};
+use tracing::{debug, debug_span, error};
 
 use dashmap::DashMap;
 use libloading::Library;
 
+use crate::admin::PluginInfo;
+use crate::metrics::Metrics;
+
 use vaccel_plugins::{
-    InvocationError, PluginDeclaration, Result, VaccelPlugin, VaccelPluginFunctions,
+    InvocationError, Node, PluginDeclaration, PluginOptions, Result, Tensor, VaccelPlugin,
+    VaccelPluginFunctions,
 };
 
 /// A proxy object that makes sure a `VaccelPlugin` cannot outlive
@@ -24,19 +30,38 @@ impl VaccelPlugin for VaccelPluginProxy {
     }
 
     fn tf_session_load(&self, model_id: u64) -> Result<()> {
-        debug!("In plugin proxy");
         self.plugin.tf_session_load(model_id)
     }
 
     fn tf_session_unload(&self, model_id: u64) -> Result<()> {
         self.plugin.tf_session_unload(model_id)
     }
+
+    fn tf_session_run(
+        &self,
+        model_id: u64,
+        inputs: &[(Node, Tensor)],
+        outputs: &[Node],
+    ) -> Result<Vec<Tensor>> {
+        self.plugin.tf_session_run(model_id, inputs, outputs)
+    }
+
+    fn init(&self, options: &PluginOptions) -> Result<()> {
+        self.plugin.init(options)
+    }
+
+    fn shutdown(&self) -> Result<()> {
+        self.plugin.shutdown()
+    }
 }
 
-#[derive(Default)]
 pub(crate) struct Plugins {
     implementations: DashMap<VaccelPluginFunctions, Vec<Arc<VaccelPluginProxy>>>,
     libraries: Vec<Arc<Library>>,
+    /// Paths the plugins were loaded from, in order, with their options
+    paths: Vec<(OsString, PluginOptions)>,
+    plugins: Vec<Arc<VaccelPluginProxy>>,
+    metrics: Arc<Metrics>,
 }
 
 impl VaccelPlugin for Plugins {
@@ -45,41 +70,88 @@ impl VaccelPlugin for Plugins {
     }
 
     fn tf_session_load(&self, model_id: u64) -> Result<()> {
-        debug!("Looking for plugin that implements tf_session_load");
-        match self
-            .implementations
-            .get(&VaccelPluginFunctions::TFSessionLoad)
-        {
-            None => {
-                error!("Could not find plugin");
-                Err(InvocationError::NotImplemented)
-            }
-            Some(plugin) => {
-                debug!("Calling implementation from {}", plugin[0].name);
-                plugin[0].tf_session_load(model_id)
-            }
-        }
+        self.dispatch(VaccelPluginFunctions::TFSessionLoad, |plugin| {
+            plugin.tf_session_load(model_id)
+        })
     }
 
     fn tf_session_unload(&self, model_id: u64) -> Result<()> {
-        match self
-            .implementations
-            .get(&VaccelPluginFunctions::TFSessionUnload)
-        {
-            None => Err(InvocationError::NotImplemented),
-            Some(plugin) => plugin[0].tf_session_unload(model_id),
+        self.dispatch(VaccelPluginFunctions::TFSessionUnload, |plugin| {
+            plugin.tf_session_unload(model_id)
+        })
+    }
+
+    fn tf_session_run(
+        &self,
+        model_id: u64,
+        inputs: &[(Node, Tensor)],
+        outputs: &[Node],
+    ) -> Result<Vec<Tensor>> {
+        self.dispatch(VaccelPluginFunctions::TFSessionRun, |plugin| {
+            plugin.tf_session_run(model_id, inputs, outputs)
+        })
+    }
+
+    /// Shut every plugin down, returning the first error
+    fn shutdown(&self) -> Result<()> {
+        let mut res = Ok(());
+        for plugin in self.plugins.iter() {
+            debug!("Shutting down plugin {}", plugin.name);
+            if let Err(e) = plugin.shutdown() {
+                error!("Could not shut down plugin {}: {}", plugin.name, e);
+                res = res.and(Err(e));
+            }
         }
+
+        res
     }
 }
 
 impl Plugins {
-    pub fn new() -> Self {
-        Plugins::default()
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Plugins {
+            implementations: DashMap::new(),
+            libraries: Vec::new(),
+            paths: Vec::new(),
+            plugins: Vec::new(),
+            metrics,
+        }
+    }
+
+    /// Call `function` on the first plugin implementing it
+    fn dispatch<T, F>(&self, function: VaccelPluginFunctions, call: F) -> Result<T>
+    where
+        F: FnOnce(&VaccelPluginProxy) -> Result<T>,
+    {
+        let plugins = match self.implementations.get(&function) {
+            Some(plugins) => plugins,
+            None => {
+                error!("Could not find plugin implementing {:?}", function);
+                return Err(InvocationError::NotImplemented);
+            }
+        };
+
+        let plugin = &plugins[0];
+        let _span = debug_span!("plugin", plugin = %plugin.name, ?function).entered();
+        debug!("Calling plugin");
+        let start = Instant::now();
+        let res = call(plugin);
+        if let Err(ref e) = res {
+            debug!(error = %e, "Plugin call failed");
+        }
+        self.metrics
+            .observe_plugin(&plugin.name, function, start.elapsed(), res.as_ref().err());
+
+        res
     }
 
-    pub unsafe fn load<P: AsRef<OsStr>>(&mut self, library_path: P) -> crate::Result<()> {
+    pub unsafe fn load<P: AsRef<OsStr>>(
+        &mut self,
+        library_path: P,
+        options: &PluginOptions,
+    ) -> crate::Result<()> {
         // Load the plugin library
-        let lib = Arc::new(Library::new(library_path)?);
+        let lib = Arc::new(Library::new(library_path.as_ref())?);
 
         let decl = lib
             .get::<*mut PluginDeclaration>(b"plugin_declaration\0")?
@@ -100,6 +172,9 @@ impl Plugins {
         // and link it in our functions DashMap
         let plugin = Arc::new(registrar.plugin.unwrap());
         debug!("Registered plugin: {}", plugin.name);
+        plugin
+            .init(options)
+            .map_err(|e| crate::Error::Plugin(format!("{}: {}", plugin.name, e)))?;
 
         for func in plugin.clone().supported() {
             debug!(
@@ -117,9 +192,45 @@ impl Plugins {
 
         // and make sure keeps a reference to the library
         self.libraries.push(lib);
+        self.paths
+            .push((library_path.as_ref().to_os_string(), options.clone()));
+        self.plugins.push(plugin);
 
         Ok(())
     }
+
+    /// Load the same plugins again, from the paths they were loaded from.
+    /// Libraries still loaded are not read again from disk.
+    pub unsafe fn reload(&self) -> crate::Result<Plugins> {
+        let mut plugins = Plugins::new(self.metrics.clone());
+        for (path, options) in self.paths.iter() {
+            plugins.load(path, options)?;
+        }
+
+        Ok(plugins)
+    }
+
+    /// Status of the loaded plugins
+    pub fn info(&self) -> Vec<PluginInfo> {
+        self.plugins
+            .iter()
+            .zip(self.paths.iter())
+            .map(|(plugin, (path, _))| PluginInfo {
+                name: plugin.name.clone(),
+                path: PathBuf::from(path),
+                functions: plugin
+                    .supported()
+                    .iter()
+                    .map(|f| format!("{:?}", f))
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// Names of the loaded plugins
+    pub fn names(&self) -> Vec<String> {
+        self.plugins.iter().map(|p| p.name.clone()).collect()
+    }
 }
 
 struct PluginRegistrar {
@@ -0,0 +1,253 @@
+//! Per-tenant quotas
+//!
+//! Quotas bound what the clients of a single principal may hold on the
+//! agent at once, so that one misbehaving guest cannot starve the others.
+//! Connections are charged to the tenant they authenticated as or, when the
+//! agent does not require authentication, to the user id of UNIX socket
+//! clients, the context id of vsock clients or the address of TCP clients.
+//! In-process clients are not subject to quotas.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use crate::server::Peer;
+use crate::{Error, Result};
+
+/// Whom the usage of a connection is charged to
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Principal {
+    /// An authenticated tenant, by name
+    Tenant(String),
+    /// A UNIX socket client, by user id
+    Uid(u32),
+    /// A vsock client, by context id
+    Cid(u32),
+    /// A TCP client, by address
+    Host(IpAddr),
+}
+
+impl Principal {
+    /// The principal of an unauthenticated client, if it is subject to
+    /// quotas
+    pub(crate) fn of(peer: &Peer) -> Option<Self> {
+        match peer {
+            Peer::Local => None,
+            Peer::Unix { uid, .. } => Some(Principal::Uid(*uid)),
+            Peer::Vsock { cid } => Some(Principal::Cid(*cid)),
+            Peer::Tcp(addr) => Some(Principal::Host(addr.ip())),
+        }
+    }
+}
+
+impl fmt::Display for Principal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Principal::Tenant(name) => write!(f, "tenant {}", name),
+            Principal::Uid(uid) => write!(f, "uid {}", uid),
+            Principal::Cid(cid) => write!(f, "cid {}", cid),
+            Principal::Host(addr) => write!(f, "host {}", addr),
+        }
+    }
+}
+
+/// What a quota bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QuotaKind {
+    /// Sessions held at once
+    Sessions,
+    /// Bytes held by registered resources. Resources that refer to files
+    /// on the host are refused when this is bounded, as their files are not
+    /// accounted for.
+    ResourceBytes,
+    /// TensorFlow models loaded at once
+    LoadedModels,
+    /// Requests in flight and jobs running, long polls aside
+    ConcurrentCalls,
+}
+
+impl fmt::Display for QuotaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuotaKind::Sessions => write!(f, "sessions"),
+            QuotaKind::ResourceBytes => write!(f, "resource bytes"),
+            QuotaKind::LoadedModels => write!(f, "loaded models"),
+            QuotaKind::ConcurrentCalls => write!(f, "concurrent calls"),
+        }
+    }
+}
+
+/// Limits on what a single principal may hold at once. Unset limits are not
+/// enforced.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Quota {
+    pub max_sessions: Option<usize>,
+    pub max_resource_bytes: Option<usize>,
+    pub max_loaded_models: Option<usize>,
+    pub max_concurrent_calls: Option<usize>,
+}
+
+impl Quota {
+    fn limit(&self, kind: QuotaKind) -> Option<usize> {
+        match kind {
+            QuotaKind::Sessions => self.max_sessions,
+            QuotaKind::ResourceBytes => self.max_resource_bytes,
+            QuotaKind::LoadedModels => self.max_loaded_models,
+            QuotaKind::ConcurrentCalls => self.max_concurrent_calls,
+        }
+    }
+}
+
+/// The quotas enforced by a server: one for every principal, unless
+/// overridden for specific ones
+#[derive(Debug, Clone, Default)]
+pub struct Quotas {
+    default: Quota,
+    overrides: HashMap<Principal, Quota>,
+}
+
+impl Quotas {
+    pub fn new(default: Quota) -> Self {
+        Quotas {
+            default,
+            overrides: HashMap::new(),
+        }
+    }
+
+    /// Enforce `quota` on `principal` instead of the default one
+    pub fn set(mut self, principal: Principal, quota: Quota) -> Self {
+        self.overrides.insert(principal, quota);
+        self
+    }
+
+    /// The quota enforced on `principal`
+    pub fn get(&self, principal: &Principal) -> &Quota {
+        self.overrides.get(principal).unwrap_or(&self.default)
+    }
+}
+
+/// What a principal currently holds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub sessions: usize,
+    pub resource_bytes: usize,
+    pub loaded_models: usize,
+    pub concurrent_calls: usize,
+}
+
+impl Usage {
+    fn get_mut(&mut self, kind: QuotaKind) -> &mut usize {
+        match kind {
+            QuotaKind::Sessions => &mut self.sessions,
+            QuotaKind::ResourceBytes => &mut self.resource_bytes,
+            QuotaKind::LoadedModels => &mut self.loaded_models,
+            QuotaKind::ConcurrentCalls => &mut self.concurrent_calls,
+        }
+    }
+}
+
+/// The usage of a principal, checked against its quota
+#[derive(Debug)]
+pub(crate) struct Account {
+    principal: Principal,
+    quota: Quota,
+    usage: Mutex<Usage>,
+}
+
+impl Account {
+    pub(crate) fn new(principal: Principal, quota: Quota) -> Self {
+        Account {
+            principal,
+            quota,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    pub(crate) fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    pub(crate) fn quota(&self) -> &Quota {
+        &self.quota
+    }
+
+    pub(crate) fn usage(&self) -> Usage {
+        *self.usage.lock().unwrap()
+    }
+
+    /// Charge `amount` of `kind` to the account, failing with
+    /// `Error::QuotaExceeded` if that would take it over its quota
+    pub(crate) fn charge(&self, kind: QuotaKind, amount: usize) -> Result<()> {
+        let mut usage = self.usage.lock().unwrap();
+        let used = usage.get_mut(kind);
+
+        if let Some(limit) = self.quota.limit(kind) {
+            if used.saturating_add(amount) > limit {
+                return Err(Error::QuotaExceeded(kind));
+            }
+        }
+
+        *used = used.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Give back `amount` of `kind`, previously charged to the account
+    pub(crate) fn release(&self, kind: QuotaKind, amount: usize) {
+        let mut usage = self.usage.lock().unwrap();
+        let used = usage.get_mut(kind);
+        *used = used.saturating_sub(amount);
+    }
+}
+
+/// A request in flight charged to an account, for as long as it is alive
+pub(crate) struct Call(Arc<Account>);
+
+impl Call {
+    pub(crate) fn new(account: Arc<Account>) -> Result<Self> {
+        account.charge(QuotaKind::ConcurrentCalls, 1)?;
+        Ok(Call(account))
+    }
+}
+
+impl Drop for Call {
+    fn drop(&mut self) {
+        self.0.release(QuotaKind::ConcurrentCalls, 1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn charge_and_release() {
+        let quota = Quota {
+            max_sessions: Some(2),
+            ..Quota::default()
+        };
+        let quotas = Quotas::default().set(Principal::Cid(3), quota.clone());
+        assert_eq!(quotas.get(&Principal::Cid(3)), &quota);
+        assert_eq!(quotas.get(&Principal::Cid(4)), &Quota::default());
+
+        let account = Arc::new(Account::new(Principal::Cid(3), quota));
+        account.charge(QuotaKind::Sessions, 2).unwrap();
+        match account.charge(QuotaKind::Sessions, 1) {
+            Err(Error::QuotaExceeded(QuotaKind::Sessions)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        account.release(QuotaKind::Sessions, 1);
+        account.charge(QuotaKind::Sessions, 1).unwrap();
+
+        // Unset limits are not enforced, but usage is still accounted
+        let call = Call::new(account.clone()).unwrap();
+        account
+            .charge(QuotaKind::ResourceBytes, usize::MAX)
+            .unwrap();
+        assert_eq!(account.usage().concurrent_calls, 1);
+        drop(call);
+        assert_eq!(account.usage().concurrent_calls, 0);
+    }
+}
@@ -5,12 +5,38 @@ use crate::tensorflow::models::{TensorflowModel, TensorflowSavedModel};
 pub trait ResourceType<'a>: Serialize + Deserialize<'a> {
     /// Get id of the resource
     fn id(&self) -> u64;
+
+    /// Number of bytes the resource holds in memory
+    fn size(&self) -> usize;
+
+    /// Whether the resource refers to files on the host of the agent, which
+    /// `size` does not account for
+    fn is_path_backed(&self) -> bool;
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Resource {
     /// A TensorFlow SavedModel
     TensorflowSavedModel(TensorflowSavedModel),
     /// A TensorFlow protobuf model
     TensorFlowModel(TensorflowModel),
 }
+
+impl Resource {
+    /// Number of bytes the resource holds in memory
+    pub fn size(&self) -> usize {
+        match self {
+            Resource::TensorflowSavedModel(model) => model.size(),
+            Resource::TensorFlowModel(model) => model.size(),
+        }
+    }
+
+    /// Whether the resource refers to files on the host of the agent, which
+    /// `size` does not account for
+    pub fn is_path_backed(&self) -> bool {
+        match self {
+            Resource::TensorflowSavedModel(model) => model.is_path_backed(),
+            Resource::TensorFlowModel(model) => model.is_path_backed(),
+        }
+    }
+}
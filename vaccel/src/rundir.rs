@@ -0,0 +1,300 @@
+//! Run directories of the agent
+//!
+//! Every `Server` keeps its state in a run directory of its own, created
+//! under a root directory which may be shared by several agents. The root is
+//! either configured, or picked from the runtime directories of the user.
+//!
+//! A run directory holds a lock file with the pid of its agent, locked for
+//! as long as the agent runs. It is set up under a temporary name and only
+//! then renamed into place, so that every run directory has its lock file.
+//! Run directories left behind by agents that are gone, e.g. after a crash,
+//! are reclaimed when a new one is created. Those without a lock file may
+//! belong to a live agent predating lock files, so they are only reclaimed
+//! on request, through `reclaim_stale`.
+
+use std::env;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::raw::c_int;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use mktemp::Temp;
+use tracing::{info, warn};
+
+use crate::{Error, Result};
+
+/// Permissions of the directories we create: only the user running the
+/// agent may access them
+const MODE: u32 = 0o700;
+
+/// File marking a run directory as in use by a live agent
+const LOCK_FILE: &str = "agent.lock";
+
+/// File serializing the creation and reclaiming of the run directories
+/// under a root
+const ROOT_LOCK_FILE: &str = ".lock";
+
+/// Prefix of the temporary name of a run directory being set up
+const NEW_PREFIX: &str = ".new-";
+
+/// Root for run directories when none is configured: `/run/user/<uid>/vaccel`
+/// if the user has a runtime directory there, else `vaccel` under
+/// `$XDG_RUNTIME_DIR`, else `vaccel-<uid>` under the temporary directory
+pub fn default_root() -> PathBuf {
+    let uid = users::get_effective_uid();
+
+    let run_user = PathBuf::from(format!("/run/user/{}", uid));
+    if run_user.is_dir() {
+        return run_user.join("vaccel");
+    }
+
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if Path::new(&dir).is_absolute() => Path::new(&dir).join("vaccel"),
+        _ => env::temp_dir().join(format!("vaccel-{}", uid)),
+    }
+}
+
+/// Create `path` and its missing parents, accessible only to the current
+/// user
+pub(crate) fn create_dir(path: &Path) -> Result<()> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(MODE)
+        .create(path)
+        .map_err(|e| Error::IOError(format!("{}: {}", path.display(), e)))
+}
+
+/// Create the default root at `path`. As it may live in a shared directory,
+/// refuse to use it unless it is a directory, rather than a symlink, the
+/// current user owns, and restrict access to it in case it was created by
+/// somebody else.
+pub(crate) fn create_default_root(path: &Path) -> Result<()> {
+    create_dir(path)?;
+
+    let metadata = fs::symlink_metadata(path)?;
+    if !metadata.is_dir() {
+        return Err(Error::IOError(format!(
+            "{}: not a directory",
+            path.display()
+        )));
+    }
+    if metadata.uid() != users::get_effective_uid() {
+        return Err(Error::IOError(format!(
+            "{}: not owned by the current user",
+            path.display()
+        )));
+    }
+    if metadata.mode() & 0o777 != MODE {
+        fs::set_permissions(path, fs::Permissions::from_mode(MODE))?;
+    }
+
+    Ok(())
+}
+
+fn flock(file: &File, operation: c_int) -> io::Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+fn open_lock(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(path)
+}
+
+/// Create a run directory under `root`, reclaiming the stale ones first.
+/// The run directory stays marked as in use for as long as the returned
+/// lock file is open.
+pub(crate) fn create(root: &Path) -> Result<(PathBuf, File)> {
+    let root_lock = open_lock(&root.join(ROOT_LOCK_FILE))?;
+    flock(&root_lock, libc::LOCK_EX)?;
+
+    for path in reclaim(root, false)? {
+        info!("Reclaimed stale run directory {}", path.display());
+    }
+
+    let new = root.join(format!("{}{}", NEW_PREFIX, process::id()));
+    DirBuilder::new().mode(MODE).create(&new)?;
+    fs::set_permissions(&new, fs::Permissions::from_mode(MODE))?;
+
+    let mut lock = open_lock(&new.join(LOCK_FILE))?;
+    flock(&lock, libc::LOCK_EX | libc::LOCK_NB)?;
+    writeln!(lock, "{}", process::id())?;
+
+    let path = Temp::new_path_in(root).release();
+    fs::rename(&new, &path)?;
+
+    Ok((path, lock))
+}
+
+/// Remove the run directories under `root` that no live agent holds,
+/// returning those removed. With `legacy`, the run directories of agents
+/// predating lock files are removed as well: only ask for it when no such
+/// agent runs anymore.
+pub fn reclaim_stale(root: &Path, legacy: bool) -> Result<Vec<PathBuf>> {
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let root_lock = open_lock(&root.join(ROOT_LOCK_FILE))?;
+    flock(&root_lock, libc::LOCK_EX)?;
+
+    reclaim(root, legacy)
+}
+
+/// Whether `name` is one `mktemp` gives to directories
+fn is_temp_name(name: &str) -> bool {
+    name.len() == 32 && name.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Whether `path` only holds session directories, as run directories did
+/// before lock files
+fn is_legacy(path: &Path) -> io::Result<bool> {
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let session = entry.file_name().to_str().is_some_and(|name| {
+            name.strip_prefix("session.")
+                .is_some_and(|id| id.parse::<u64>().is_ok())
+        });
+        if !session || !entry.file_type()?.is_dir() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Whether `path` is a run directory no live agent holds. Those without a
+/// lock file are only when `legacy`, and if they look like run directories
+/// predating lock files.
+fn is_stale(path: &Path, legacy: bool) -> io::Result<bool> {
+    match File::open(path.join(LOCK_FILE)) {
+        Ok(lock) => match flock(&lock, libc::LOCK_EX | libc::LOCK_NB) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        },
+        // Nothing tells whether an agent predating lock files still uses
+        // the directory
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(legacy && is_legacy(path)?),
+        Err(e) => Err(e),
+    }
+}
+
+/// Remove the run directories under `root` that no live agent holds, along
+/// with their session directories, returning those removed. Anything under
+/// `root` that does not look like a run directory is left alone. The caller
+/// holds the lock of `root`.
+fn reclaim(root: &Path, legacy: bool) -> Result<Vec<PathBuf>> {
+    let mut reclaimed = Vec::new();
+
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().unwrap_or_default();
+        // Run directories are set up while holding the lock of the root, so
+        // those still being set up were left by an agent that crashed
+        let new = name.starts_with(NEW_PREFIX);
+        if !(new || is_temp_name(name)) || !entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let path = entry.path();
+        match is_stale(&path, legacy).map(|stale| stale || new) {
+            Ok(false) => {}
+            Ok(true) => match fs::remove_dir_all(&path) {
+                Ok(()) => reclaimed.push(path),
+                Err(e) => warn!("Could not remove {}: {}", path.display(), e),
+            },
+            Err(e) => warn!("Could not inspect {}: {}", path.display(), e),
+        }
+    }
+
+    Ok(reclaimed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn create_with_parents() {
+        let tmp = Temp::new_dir().unwrap();
+        let root = tmp.as_path().join("run/vaccel");
+
+        create_dir(&root).expect("Could not create root");
+        for dir in [tmp.as_path().join("run"), root.clone()].iter() {
+            let mode = fs::metadata(dir).unwrap().mode() & 0o777;
+            assert_eq!(mode, MODE, "{}", dir.display());
+        }
+
+        // Existing roots are left as they are, unless they are the default
+        fs::set_permissions(&root, fs::Permissions::from_mode(0o755)).unwrap();
+        create_dir(&root).unwrap();
+        assert_eq!(fs::metadata(&root).unwrap().mode() & 0o777, 0o755);
+        create_default_root(&root).unwrap();
+        assert_eq!(fs::metadata(&root).unwrap().mode() & 0o777, MODE);
+
+        // Somebody else may have planted a symlink at the default root
+        let link = tmp.as_path().join("link");
+        std::os::unix::fs::symlink(&root, &link).unwrap();
+        assert!(create_default_root(&link).is_err());
+    }
+
+    #[test]
+    fn reclaim_stale() {
+        let tmp = Temp::new_dir().unwrap();
+        let root = tmp.as_path();
+
+        let (live, _lock) = create(root).unwrap();
+        let (crashed, lock) = create(root).unwrap();
+        fs::create_dir(crashed.join("session.1")).unwrap();
+        drop(lock);
+
+        // Directories without a lock file may belong to live agents
+        // predating them
+        let legacy = root.join("0123456789abcdef0123456789abcdef");
+        fs::create_dir_all(legacy.join("session.2")).unwrap();
+        let empty = root.join("00112233445566778899aabbccddeeff");
+        fs::create_dir(&empty).unwrap();
+        let unrelated = root.join("fedcba9876543210fedcba9876543210");
+        fs::create_dir(&unrelated).unwrap();
+        fs::write(unrelated.join("data"), b"keep").unwrap();
+        fs::create_dir(root.join("other")).unwrap();
+
+        let pid = fs::read_to_string(live.join(LOCK_FILE)).unwrap();
+        assert_eq!(pid.trim(), process::id().to_string());
+
+        // An agent crashed while setting its run directory up
+        let new = root.join(format!("{}1", NEW_PREFIX));
+        fs::create_dir(&new).unwrap();
+
+        let mut reclaimed = reclaim(root, false).unwrap();
+        reclaimed.sort();
+        assert_eq!(reclaimed, vec![new, crashed]);
+
+        assert!(live.is_dir());
+        assert!(legacy.is_dir());
+        assert!(empty.is_dir());
+        assert!(unrelated.is_dir());
+        assert!(root.join("other").is_dir());
+
+        // unless the operator tells that no such agent runs anymore
+        let mut reclaimed = super::reclaim_stale(root, true).unwrap();
+        reclaimed.sort();
+        assert_eq!(reclaimed, vec![empty, legacy]);
+        assert!(live.is_dir());
+        assert!(unrelated.is_dir());
+        assert!(root.join("other").is_dir());
+    }
+}
@@ -1,23 +1,112 @@
-use std::fs;
-use std::path::Path;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{self, File};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock, Weak};
+use std::time::{Duration, Instant, SystemTime};
 
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, field, info_span, warn, Instrument, Span};
 
-use tarpc::context::Context;
-
-use mktemp::Temp;
+use futures::{future, StreamExt};
+use tarpc::context::{self, Context};
+use tarpc::server::{BaseChannel, Channel, Serve};
+use tarpc::{ClientMessage, Response, Transport};
+use tokio::runtime::Handle;
+use tokio::sync::{watch, Notify, Semaphore};
+use tokio_util::sync::CancellationToken;
 
+use crate::admin::{AccountInfo, PluginInfo, ResourceInfo, SessionInfo};
+use crate::auth::{Permission, Tenant, TokenStore};
+use crate::batch::{Operation, Output};
+use crate::event::Event;
+use crate::job::JobStatus;
+use crate::metrics::Metrics;
 use crate::plugin::*;
+use crate::quota::{Account, Call, Principal, QuotaKind, Quotas};
 use crate::resource::Resource;
+use crate::rundir;
 use crate::session::Session;
+use crate::tensorflow::{Node, Tensor};
 use crate::{Error, Result};
 
 use vaccel_plugins::VaccelPlugin;
 
+pub use vaccel_plugins::PluginOptions;
+
+/// Version of the vAccel RPC protocol, bumped on every incompatible change
+/// to `VaccelAPI`
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Protocol feature: the agent requires clients to authenticate
+pub const FEATURE_AUTH: &str = "auth";
+
+/// Protocol feature: the agent runs batches of operations
+pub const FEATURE_BATCH: &str = "batch";
+
+/// Protocol feature: the agent runs TensorFlow sessions
+pub const FEATURE_TF_SESSION_RUN: &str = "tf_session_run";
+
+/// Protocol feature: the agent runs jobs in the background
+pub const FEATURE_JOBS: &str = "jobs";
+
+/// Protocol feature: the agent delivers events to subscribed clients
+pub const FEATURE_EVENTS: &str = "events";
+
+/// Protocol feature: the agent lists its plugins
+pub const FEATURE_PLUGINS: &str = "plugins";
+
+/// Protocol feature: the agent unregisters resources
+pub const FEATURE_UNREGISTER_RESOURCE: &str = "unregister_resource";
+
+/// Protocol features every agent speaking this version supports
+const FEATURES: &[&str] = &[
+    FEATURE_BATCH,
+    FEATURE_TF_SESSION_RUN,
+    FEATURE_JOBS,
+    FEATURE_EVENTS,
+    FEATURE_PLUGINS,
+    FEATURE_UNREGISTER_RESOURCE,
+];
+
+/// Events kept for a subscribed client that is not polling. Older events
+/// are dropped first.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// Jobs are not bound by the deadline of the request that submitted them,
+/// but must still start within this long
+const JOB_DEADLINE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How long to wait for the first request of a client that is rejected
+const REJECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Jobs a single connection may have at once, running or done but not yet
+/// reported to the client
+const MAX_JOBS: usize = 64;
+
+/// Exchanged by client and agent when a connection starts, so that
+/// incompatible peers fail early and with a meaningful error
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hello {
+    /// Protocol version spoken by the sender
+    pub version: u32,
+    /// Optional protocol features supported by the sender
+    pub features: Vec<String>,
+}
+
 #[tarpc::service]
 pub trait VaccelAPI {
+    /// Negotiate the protocol version. This must stay compatible across
+    /// protocol versions.
+    async fn hello(client: Hello) -> Result<Hello>;
+
+    /// Authenticate the connection with a token, returning the tenant it
+    /// maps to
+    async fn authenticate(token: String) -> Result<Tenant>;
+
     /// Create a new vAccel session
     async fn new_session() -> Result<u64>;
 
@@ -25,7 +114,11 @@ pub trait VaccelAPI {
     async fn destroy_session(session: u64) -> Result<()>;
 
     /// Register a new vAccel resource
-    async fn register_resource(resource: Resource) -> u64;
+    async fn register_resource(resource: Resource) -> Result<u64>;
+
+    /// Release a resource registered over this connection. Models must be
+    /// unloaded first.
+    async fn unregister_resource(resource: u64) -> Result<()>;
 
     // TensorFlow related API
     /// Load a TensorFlow model in memory creating a session
@@ -33,55 +126,610 @@ pub trait VaccelAPI {
 
     /// Unload TensorFlow session
     async fn tf_session_unload(model_id: u64) -> Result<()>;
+
+    /// Run inference on a loaded TensorFlow model
+    async fn tf_session_run(
+        model_id: u64,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    ) -> Result<Vec<Tensor>>;
+
+    /// Execute `operations` in order, returning the result of each one. With
+    /// `stop_on_error`, execution stops at the first failed operation, which
+    /// is the last one with a result.
+    async fn batch(operations: Vec<Operation>, stop_on_error: bool) -> Result<Vec<Result<Output>>>;
+
+    /// Run `operation` in the background on behalf of a session, returning
+    /// the id of the job
+    async fn submit_job(session: u64, operation: Operation) -> Result<u64>;
+
+    /// Current status of a job. Once reported done, the job is forgotten.
+    async fn poll_job(job: u64) -> Result<JobStatus>;
+
+    /// Wait up to `timeout`, and no longer than the deadline of the request,
+    /// for a job to complete, returning its status. Once reported done, the
+    /// job is forgotten.
+    async fn wait_job(job: u64, timeout: Duration) -> Result<JobStatus>;
+
+    /// Cancel a job, if still running, and forget about it
+    async fn cancel_job(job: u64) -> Result<()>;
+
+    /// Wait up to `timeout`, and no longer than the deadline of the request,
+    /// for events, returning those pending. The first call subscribes the
+    /// connection to events.
+    async fn next_events(timeout: Duration) -> Result<Vec<Event>>;
+
+    /// Plugins loaded by the agent
+    async fn plugins() -> Result<Vec<Plugin>>;
+}
+
+/// Identity of the client on the other end of a connection
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Peer {
+    /// An in-process client
+    Local,
+    /// A client on a UNIX socket, as reported by `SO_PEERCRED`
+    Unix {
+        uid: u32,
+        gid: u32,
+        pid: Option<i32>,
+    },
+    /// A client on a vsock socket
+    Vsock { cid: u32 },
+    /// A client on a TCP socket
+    Tcp(SocketAddr),
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Local => write!(f, "local"),
+            Peer::Unix { uid, gid, pid } => match pid {
+                Some(pid) => write!(f, "unix(uid={}, gid={}, pid={})", uid, gid, pid),
+                None => write!(f, "unix(uid={}, gid={})", uid, gid),
+            },
+            Peer::Vsock { cid } => write!(f, "vsock(cid={})", cid),
+            Peer::Tcp(addr) => write!(f, "tcp({})", addr),
+        }
+    }
 }
 
+/// A plugin loaded by the agent, as its clients see it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Plugin {
+    pub name: String,
+    /// Functions the plugin implements
+    pub functions: Vec<String>,
+}
+
+/// Limits protecting the agent from overload. Requests beyond them fail
+/// with `Error::Busy`.
+#[derive(Debug, Clone, Default)]
+pub struct Limits {
+    /// Maximum number of concurrent client connections
+    pub max_connections: Option<usize>,
+    /// Maximum number of requests in flight on a single connection
+    pub max_requests_per_connection: Option<usize>,
+    /// Maximum number of requests in flight across all connections
+    pub max_requests: Option<usize>,
+}
+
+/// The vAccel agent state, shared by all client connections
 #[derive(Clone)]
 pub struct Server(Arc<ServerState>);
 
 pub struct ServerState {
-    rundir: mktemp::Temp,
+    /// Removed on shutdown, or when the state is dropped
+    rundir: PathBuf,
+    /// Marks `rundir` as in use, for as long as it is open
+    _rundir_lock: File,
+    connection_id: AtomicU64,
+    connections: DashMap<u64, Weak<ConnectionState>>,
     session_id: AtomicU64,
     sessions: DashMap<u64, Arc<Session>>,
-    plugins: Arc<Plugins>,
+    resource_id: AtomicU64,
+    resources: DashMap<u64, Arc<Resource>>,
+    plugins: RwLock<Arc<Plugins>>,
+    /// Tokens clients must authenticate with, if authentication is enabled
+    tokens: Option<TokenStore>,
+    limits: Limits,
+    /// Permits for open connections, when their number is limited
+    connection_slots: Option<Arc<Semaphore>>,
+    /// Permits for requests in flight, when their number is limited
+    requests: Option<Arc<Semaphore>>,
+    quotas: Quotas,
+    /// Usage of every principal that connected, charged against its quota
+    accounts: DashMap<Principal, Arc<Account>>,
+    in_flight: AtomicUsize,
+    /// Notified when the last request in flight completes
+    idle: Notify,
+    /// Cancelled when the server starts shutting down, waking the requests
+    /// waiting on behalf of clients
+    shutting_down: CancellationToken,
+    metrics: Arc<Metrics>,
+}
+
+#[derive(Default)]
+pub struct ServerBuilder {
+    tokens: Option<TokenStore>,
+    limits: Limits,
+    quotas: Quotas,
+    rundir: Option<PathBuf>,
+    plugins: Vec<(PathBuf, PluginOptions)>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        ServerBuilder::default()
+    }
+
+    /// Require remote clients to authenticate with one of `tokens`
+    pub fn tokens(mut self, tokens: TokenStore) -> Self {
+        self.tokens = Some(tokens);
+        self
+    }
+
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Quotas enforced on every tenant
+    pub fn quotas(mut self, quotas: Quotas) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Directory under which the agent creates its run directory, created
+    /// if missing. Defaults to `rundir::default_root()`
+    pub fn rundir(mut self, root: PathBuf) -> Self {
+        self.rundir = Some(root);
+        self
+    }
+
+    /// Load the plugin at `path`, configured with `options`. When no plugin
+    /// is given, the noop plugin is loaded from the working directory.
+    pub fn plugin(mut self, path: PathBuf, options: PluginOptions) -> Self {
+        self.plugins.push((path, options));
+        self
+    }
+
+    pub fn build(self) -> Result<Server> {
+        let root = match self.rundir {
+            Some(root) => {
+                rundir::create_dir(&root)?;
+                root
+            }
+            None => {
+                let root = rundir::default_root();
+                rundir::create_default_root(&root)?;
+                root
+            }
+        };
+
+        let (rundir, rundir_lock) = rundir::create(&root)?;
+
+        let metrics = Arc::new(Metrics::new());
+        let mut plugins = Plugins::new(metrics.clone());
+        unsafe {
+            if self.plugins.is_empty() {
+                plugins.load("./libvaccel_noop.so", &PluginOptions::new())?;
+            }
+            for (path, options) in self.plugins.iter() {
+                plugins.load(path, options)?;
+            }
+        }
+
+        Ok(Server(Arc::new(ServerState {
+            rundir,
+            _rundir_lock: rundir_lock,
+            connection_id: AtomicU64::new(1),
+            connections: DashMap::new(),
+            session_id: AtomicU64::new(1),
+            sessions: DashMap::new(),
+            resource_id: AtomicU64::new(1),
+            resources: DashMap::new(),
+            plugins: RwLock::new(Arc::new(plugins)),
+            tokens: self.tokens,
+            connection_slots: self
+                .limits
+                .max_connections
+                .map(|max| Arc::new(Semaphore::new(max))),
+            requests: self
+                .limits
+                .max_requests
+                .map(|max| Arc::new(Semaphore::new(max))),
+            limits: self.limits,
+            quotas: self.quotas,
+            accounts: DashMap::new(),
+            in_flight: AtomicUsize::new(0),
+            idle: Notify::new(),
+            shutting_down: CancellationToken::new(),
+            metrics,
+        })))
+    }
 }
 
 impl Server {
     pub fn new() -> Result<Self> {
-        let vaccel_path =
-            Path::new(&format!("/run/user/{}/vaccel", users::get_current_uid())).to_path_buf();
+        ServerBuilder::new().build()
+    }
+
+    /// Register a new client connection with the server. The returned
+    /// `Connection` serves the vAccel API for that client.
+    pub fn connection(&self, peer: Peer) -> Connection {
+        let id = self.0.connection_id.fetch_add(1, Ordering::SeqCst);
+        debug!(connection = id, %peer, "New connection");
+
+        let account = Principal::of(&peer).map(|principal| self.account(principal));
+        let state = Arc::new(ConnectionState {
+            id,
+            peer,
+            server: self.clone(),
+            tenant: RwLock::new(None),
+            account: RwLock::new(account),
+            sessions: DashSet::new(),
+            resources: DashSet::new(),
+            models: DashSet::new(),
+            job_id: AtomicU64::new(1),
+            jobs: DashMap::new(),
+            events: Mutex::new(None),
+            events_ready: Notify::new(),
+        });
+
+        self.0.connections.insert(id, Arc::downgrade(&state));
+        Connection(state)
+    }
+
+    /// Number of client connections currently open
+    pub fn connections(&self) -> usize {
+        self.0.connections.len()
+    }
+
+    /// Serve the vAccel API to a new client over `transport`, until the
+    /// client goes away. Clients beyond the connection limit get
+    /// `Error::Busy` for their first request, and are then hung up on.
+    pub async fn serve<T>(self, transport: T, peer: Peer)
+    where
+        T: Transport<Response<VaccelAPIResponse>, ClientMessage<VaccelAPIRequest>> + Send + 'static,
+    {
+        // Held for as long as the connection is served
+        let _slot = match self
+            .0
+            .connection_slots
+            .clone()
+            .map(Semaphore::try_acquire_owned)
+            .transpose()
+        {
+            Ok(slot) => slot,
+            Err(_) => {
+                warn!("Rejecting connection from {}: too many connections", peer);
+                reject(transport, Error::Busy).await;
+                return;
+            }
+        };
 
-        // If vAccel rundir path does not exist, create it
-        if !vaccel_path.is_dir() {
-            fs::create_dir(&vaccel_path)?;
+        let channel = BaseChannel::with_defaults(transport);
+        let limits = &self.0.limits;
+        let connection = self.connection(peer);
+        let state = Arc::downgrade(&connection.0);
+        let requests = self.0.requests.clone();
+        let connection_requests = limits
+            .max_requests_per_connection
+            .map(|max| Arc::new(Semaphore::new(max)));
+
+        let serve = move |ctx: Context, request| {
+            // Hold the permits until the response is ready
+            let permits = (
+                requests.clone().map(Semaphore::try_acquire_owned),
+                connection_requests
+                    .clone()
+                    .map(Semaphore::try_acquire_owned),
+            );
+            let connection = connection.clone();
+            let method = request_method(&request);
+            // Long polls do not keep the agent busy, and jobs are charged for
+            // as long as they run instead
+            let charged = !matches!(
+                request,
+                VaccelAPIRequest::NextEvents { .. }
+                    | VaccelAPIRequest::WaitJob { .. }
+                    | VaccelAPIRequest::SubmitJob { .. }
+            );
+            let span = info_span!(
+                "rpc",
+                connection = connection.id(),
+                request = %ctx.trace_id(),
+                method,
+                session = field::Empty,
+                model = field::Empty,
+            );
+            async move {
+                let server = connection.server().clone();
+                let start = Instant::now();
+
+                let response = if server.0.shutting_down.is_cancelled() {
+                    error_response(&request, Error::Busy)
+                } else {
+                    match permits {
+                        (Some(Err(_)), _) | (_, Some(Err(_))) => {
+                            error_response(&request, Error::Busy)
+                        }
+                        _permits => match connection
+                            .account()
+                            .filter(|_| charged)
+                            .map(Call::new)
+                            .transpose()
+                        {
+                            Err(e) => error_response(&request, e),
+                            Ok(_call) => {
+                                let _request = InFlightRequest::new(server.clone());
+                                connection.serve().serve(ctx, request).await
+                            }
+                        },
+                    }
+                };
+
+                server
+                    .0
+                    .metrics
+                    .observe_rpc(method, start.elapsed(), response_error(&response));
+                response
+            }
+            .instrument(span)
+        };
+
+        channel.execute(serve).await;
+
+        // Jobs do not keep the connection alive, so that what the client
+        // holds is reclaimed once it goes away, as soon as no plugin call
+        // is running on its behalf
+        if let Some(state) = state.upgrade() {
+            state.cancel_jobs();
         }
+    }
 
-        let rundir = Temp::new_dir_in(&Path::new(&vaccel_path))
-            .map_err(|e| Error::IOError(e.to_string()))?;
+    /// Current metrics of the server, in the Prometheus text format
+    pub fn metrics(&self) -> String {
+        let state = &self.0;
+        let metrics = &state.metrics;
 
-        let mut plugins = Plugins::new();
-        unsafe {
-            plugins.load("./libvaccel_noop.so")?;
+        let resource_bytes: usize = state.resources.iter().map(|r| r.size()).sum();
+        metrics.sessions.set(state.sessions.len() as i64);
+        metrics.resource_bytes.set(resource_bytes as i64);
+        metrics.connections.set(self.connections() as i64);
+
+        metrics.encode()
+    }
+
+    /// Stop serving and release everything the server holds: wake the
+    /// requests waiting on jobs or events, cancel all jobs and wait up to
+    /// `timeout` for the requests and jobs in flight, then unload all
+    /// models, shut the plugins down and destroy all sessions. Requests
+    /// arriving in the meantime fail with `Error::Busy`.
+    pub async fn shutdown(&self, timeout: Duration) {
+        let state = &self.0;
+        state.shutting_down.cancel();
+
+        // Jobs already in a plugin call run to completion, and are waited
+        // for along with requests
+        for connection in self.live_connections() {
+            connection.cancel_jobs();
         }
 
-        Ok(Server {
-            0: Arc::new(ServerState {
-                rundir,
-                session_id: AtomicU64::new(1),
-                sessions: DashMap::new(),
-                plugins: Arc::new(plugins),
-            }),
+        let drained = tokio::time::timeout(timeout, async {
+            loop {
+                let idle = state.idle.notified();
+                tokio::pin!(idle);
+                idle.as_mut().enable();
+
+                if state.in_flight.load(Ordering::SeqCst) == 0 {
+                    break;
+                }
+                idle.await;
+            }
         })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "Shutting down with {} requests still in flight",
+                state.in_flight.load(Ordering::SeqCst)
+            );
+        }
+
+        let plugins = self.plugins();
+        for connection in self.live_connections() {
+            connection.unload_models(&plugins);
+        }
+
+        if let Err(e) = plugins.shutdown() {
+            warn!("Could not shut plugins down: {}", e);
+        }
+
+        debug!("Destroying {} sessions", state.sessions.len());
+        state.sessions.clear();
+        state.resources.clear();
+
+        if let Err(e) = fs::remove_dir_all(state.rundir.as_path()) {
+            warn!(
+                "Could not remove {}: {}",
+                state.rundir.as_path().display(),
+                e
+            );
+        }
+    }
+
+    /// The account of `principal`, opened on first use
+    fn account(&self, principal: Principal) -> Arc<Account> {
+        let quota = self.0.quotas.get(&principal).clone();
+        self.0
+            .accounts
+            .entry(principal.clone())
+            .or_insert_with(|| Arc::new(Account::new(principal, quota)))
+            .clone()
     }
 
     fn next_id(&self) -> u64 {
         self.0.session_id.fetch_add(1, Ordering::SeqCst)
     }
 
+    fn next_resource_id(&self) -> u64 {
+        self.0.resource_id.fetch_add(1, Ordering::SeqCst)
+    }
+
     fn remove_session(&self, session_id: &u64) -> Option<Arc<Session>> {
-        match self.0.sessions.remove(session_id) {
-            None => None,
-            Some((_, session)) => Some(session),
+        self.0
+            .sessions
+            .remove(session_id)
+            .map(|(_, session)| session)
+    }
+
+    fn plugins(&self) -> Arc<Plugins> {
+        self.0.plugins.read().unwrap().clone()
+    }
+
+    /// Live client connections
+    fn live_connections(&self) -> Vec<Arc<ConnectionState>> {
+        self.0
+            .connections
+            .iter()
+            .filter_map(|e| e.value().upgrade())
+            .collect()
+    }
+
+    /// Destroy a session on behalf of the agent, notifying the client that
+    /// owns it
+    pub fn reap_session(&self, session_id: u64) -> Result<()> {
+        let session = self
+            .get_session(&session_id)
+            .ok_or(Error::InvalidArgument)?;
+        debug!("Reaping session {}", session_id);
+
+        let owner = session.owner().and_then(|id| {
+            self.0
+                .connections
+                .get(&id)
+                .and_then(|e| e.value().upgrade())
+        });
+        drop(session);
+
+        match owner {
+            None => {
+                self.remove_session(&session_id);
+            }
+            Some(connection) => {
+                connection.remove_session(session_id)?;
+                connection.push_event(Event::SessionReaped {
+                    session: session_id,
+                });
+            }
         }
+
+        Ok(())
+    }
+
+    /// Load all plugins again, notifying every client. Models loaded
+    /// through the previous instances are unloaded, and clients have to
+    /// load them again. The previous instances are then shut down.
+    ///
+    /// Plugins are instantiated anew from the libraries already loaded:
+    /// loading a library from the same path again yields the one in memory,
+    /// so changes to the library on disk are not picked up.
+    pub fn reload_plugins(&self) -> Result<()> {
+        let plugins = Arc::new(unsafe { self.plugins().reload()? });
+        let names = plugins.names();
+        let previous = std::mem::replace(&mut *self.0.plugins.write().unwrap(), plugins);
+
+        for connection in self.live_connections() {
+            connection.unload_models(&previous);
+        }
+        if let Err(e) = previous.shutdown() {
+            warn!("Could not shut previous plugins down: {}", e);
+        }
+
+        for name in names {
+            debug!("Reloaded plugin {}", name);
+            for connection in self.live_connections() {
+                connection.push_event(Event::PluginReloaded { name: name.clone() });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sessions held by the server, with the connection that owns them
+    pub fn session_info(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<_> = self
+            .0
+            .sessions
+            .iter()
+            .map(|session| {
+                let connection = session.owner().and_then(|id| {
+                    self.0
+                        .connections
+                        .get(&id)
+                        .and_then(|e| e.value().upgrade())
+                });
+                SessionInfo {
+                    id: session.id(),
+                    connection: connection.as_ref().map(|c| c.id),
+                    peer: connection.as_ref().map(|c| c.peer.to_string()),
+                    rundir: session.rundir().map(Path::to_path_buf),
+                }
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+
+        sessions
+    }
+
+    /// Resources registered with the server, with the connection that owns
+    /// them
+    pub fn resource_info(&self) -> Vec<ResourceInfo> {
+        let connections = self.live_connections();
+        let mut resources: Vec<_> = self
+            .0
+            .resources
+            .iter()
+            .map(|resource| {
+                let id = *resource.key();
+                let kind = match **resource {
+                    Resource::TensorflowSavedModel(_) => "TensorflowSavedModel",
+                    Resource::TensorFlowModel(_) => "TensorFlowModel",
+                };
+                ResourceInfo {
+                    id,
+                    kind: kind.to_string(),
+                    size: resource.size(),
+                    connection: connections
+                        .iter()
+                        .find(|c| c.resources.contains(&id))
+                        .map(|c| c.id),
+                    loaded: connections.iter().any(|c| c.models.contains(&id)),
+                }
+            })
+            .collect();
+        resources.sort_by_key(|resource| resource.id);
+
+        resources
+    }
+
+    /// Plugins loaded by the server
+    pub fn plugin_info(&self) -> Vec<PluginInfo> {
+        self.plugins().info()
+    }
+
+    /// Usage of every principal that connected to the server
+    pub fn account_info(&self) -> Vec<AccountInfo> {
+        self.0
+            .accounts
+            .iter()
+            .map(|account| AccountInfo {
+                principal: account.principal().clone(),
+                quota: account.quota().clone(),
+                usage: account.usage(),
+            })
+            .collect()
     }
 
     pub fn get_session(&self, session_id: &u64) -> Option<Arc<Session>> {
@@ -90,44 +738,1460 @@ impl Server {
             .get(session_id)
             .map(|r| Arc::clone(r.value()))
     }
+
+    pub fn get_resource(&self, resource_id: &u64) -> Option<Arc<Resource>> {
+        self.0
+            .resources
+            .get(resource_id)
+            .map(|r| Arc::clone(r.value()))
+    }
+}
+
+impl Drop for ServerState {
+    fn drop(&mut self) {
+        if self.rundir.exists() {
+            let _ = fs::remove_dir_all(&self.rundir);
+        }
+    }
+}
+
+/// A single client connection to a `Server`
+#[derive(Clone)]
+pub struct Connection(Arc<ConnectionState>);
+
+pub struct ConnectionState {
+    id: u64,
+    peer: Peer,
+    server: Server,
+    /// Tenant the connection authenticated as
+    tenant: RwLock<Option<Tenant>>,
+    /// Account the usage of the connection is charged to, unless it is not
+    /// subject to quotas
+    account: RwLock<Option<Arc<Account>>>,
+    /// Sessions created over this connection
+    sessions: DashSet<u64>,
+    /// Resources registered over this connection
+    resources: DashSet<u64>,
+    /// TensorFlow models loaded over this connection
+    models: DashSet<u64>,
+    job_id: AtomicU64,
+    /// Jobs submitted over this connection, until they are reported done,
+    /// cancelled or their session is destroyed
+    jobs: DashMap<u64, Job>,
+    /// Events not yet delivered to the client, or `None` until it
+    /// subscribes
+    events: Mutex<Option<VecDeque<Event>>>,
+    events_ready: Notify,
+}
+
+impl ConnectionState {
+    /// `status` of job `job_id`, forgetting the job once it is done as the
+    /// client is then told about it
+    fn report_job(&self, job_id: u64, status: JobStatus) -> JobStatus {
+        if !status.is_running() {
+            self.jobs.remove(&job_id);
+        }
+        status
+    }
+
+    /// Cancel and forget all jobs of this connection
+    fn cancel_jobs(&self) {
+        for job in self.jobs.iter() {
+            job.cancellation.cancel();
+        }
+        self.jobs.clear();
+    }
+
+    /// Unload the models loaded over this connection through `plugins`,
+    /// giving them back to the account of the connection
+    fn unload_models(&self, plugins: &Plugins) {
+        let models: Vec<u64> = self.models.iter().map(|id| *id).collect();
+        for model_id in models {
+            if self.models.remove(&model_id).is_none() {
+                continue;
+            }
+
+            debug!(connection = self.id, model = model_id, "Unloading model");
+            if let Err(e) = plugins.tf_session_unload(model_id) {
+                warn!("Could not unload model {}: {}", model_id, e);
+            }
+            self.release(QuotaKind::LoadedModels, 1);
+        }
+    }
+
+    /// Forget a session of this connection, cancelling its jobs
+    fn remove_session(&self, session_id: u64) -> Result<()> {
+        if self.sessions.remove(&session_id).is_none() {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.jobs.retain(|_, job| {
+            if job.session == session_id {
+                job.cancellation.cancel();
+            }
+            job.session != session_id
+        });
+
+        match self.server.remove_session(&session_id) {
+            None => Err(Error::InvalidArgument),
+            Some(_) => {
+                self.release(QuotaKind::Sessions, 1);
+                Ok(())
+            }
+        }
+    }
+
+    /// Give back `amount` of `kind` to the account of the connection
+    fn release(&self, kind: QuotaKind, amount: usize) {
+        if let Some(ref account) = *self.account.read().unwrap() {
+            account.release(kind, amount);
+        }
+    }
+
+    /// Queue `event` for the client, if it subscribed to events
+    fn push_event(&self, event: Event) {
+        let mut events = self.events.lock().unwrap();
+        if let Some(ref mut events) = *events {
+            if events.len() == MAX_PENDING_EVENTS {
+                events.pop_front();
+            }
+            events.push_back(event);
+            self.events_ready.notify_one();
+        }
+    }
+}
+
+/// Answer the first request of a client over `transport` with `error`, then
+/// hang up
+async fn reject<T>(transport: T, error: Error)
+where
+    T: Transport<Response<VaccelAPIResponse>, ClientMessage<VaccelAPIRequest>> + Send + 'static,
+{
+    let mut requests = Box::pin(BaseChannel::with_defaults(transport).requests());
+
+    let _ = tokio::time::timeout(REJECT_TIMEOUT, async {
+        if let Some(Ok(request)) = requests.next().await {
+            request
+                .execute(|_, request| future::ready(error_response(&request, error)))
+                .await;
+            // Write the response out, until the client hangs up or sends
+            // another request
+            let _ = requests.next().await;
+        }
+    })
+    .await;
+}
+
+/// An operation running in the background on behalf of a session
+struct Job {
+    session: u64,
+    status: watch::Receiver<JobStatus>,
+    cancellation: CancellationToken,
+}
+
+impl Connection {
+    pub fn id(&self) -> u64 {
+        self.0.id
+    }
+
+    pub fn peer(&self) -> &Peer {
+        &self.0.peer
+    }
+
+    pub fn tenant(&self) -> Option<Tenant> {
+        self.0.tenant.read().unwrap().clone()
+    }
+
+    fn server(&self) -> &Server {
+        &self.0.server
+    }
+
+    fn account(&self) -> Option<Arc<Account>> {
+        self.0.account.read().unwrap().clone()
+    }
+
+    /// Charge the usage of the connection to `principal` from now on. A
+    /// connection cannot move what it holds to another account.
+    fn charge_to(&self, principal: Principal) -> Result<()> {
+        let mut account = self.0.account.write().unwrap();
+        if account.as_ref().map(|a| a.principal()) == Some(&principal) {
+            return Ok(());
+        }
+
+        let state = &self.0;
+        if !(state.sessions.is_empty() && state.resources.is_empty() && state.models.is_empty()) {
+            warn!(
+                "Refusing to switch to {} while holding sessions or resources",
+                principal
+            );
+            return Err(Error::PermissionDenied);
+        }
+
+        *account = Some(self.server().account(principal));
+        Ok(())
+    }
+
+    /// Charge `amount` of `kind` to the account of the connection
+    fn charge(&self, kind: QuotaKind, amount: usize) -> Result<()> {
+        match self.account() {
+            None => Ok(()),
+            Some(account) => account.charge(kind, amount).inspect_err(|_| {
+                warn!(principal = %account.principal(), "Quota exceeded: {}", kind);
+            }),
+        }
+    }
+
+    /// Check that the client may perform operations requiring `permission`.
+    /// In-process clients and agents without a token store allow everything.
+    fn authorize(&self, permission: Permission) -> Result<()> {
+        self.authenticated()?;
+
+        match *self.0.tenant.read().unwrap() {
+            Some(ref tenant) if !tenant.allows(permission) => Err(Error::PermissionDenied),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fail unless the client has authenticated, if the agent requires it
+    fn authenticated(&self) -> Result<()> {
+        if self.server().0.tokens.is_none() || self.0.peer == Peer::Local {
+            return Ok(());
+        }
+
+        match *self.0.tenant.read().unwrap() {
+            None => Err(Error::Unauthenticated),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Look up a resource registered over this connection
+    fn resource(&self, resource_id: u64) -> Result<Arc<Resource>> {
+        if !self.0.resources.contains(&resource_id) {
+            return Err(Error::InvalidArgument);
+        }
+
+        self.server()
+            .get_resource(&resource_id)
+            .ok_or(Error::InvalidArgument)
+    }
+
+    /// Execute a single operation of a batch, given the results of the
+    /// operations preceding it
+    async fn operation(
+        &self,
+        ctx: Context,
+        operation: Operation,
+        results: &[Result<Output>],
+    ) -> Result<Output> {
+        // The sessions and models operations are about are recorded in
+        // spans of their own, as a batch may involve several
+        let span = info_span!("operation", session = field::Empty, model = field::Empty);
+
+        async move {
+            match operation {
+                Operation::NewSession => self.clone().new_session(ctx).await.map(Output::Session),
+                Operation::DestroySession(session) => {
+                    let id = session.session(results)?;
+                    self.clone().destroy_session(ctx, id).await?;
+                    Ok(Output::Done)
+                }
+                Operation::RegisterResource(resource) => self
+                    .clone()
+                    .register_resource(ctx, resource)
+                    .await
+                    .map(Output::Resource),
+                Operation::TfSessionLoad(model) => {
+                    let id = model.resource(results)?;
+                    self.clone().tf_session_load(ctx, id).await?;
+                    Ok(Output::Done)
+                }
+                Operation::TfSessionRun {
+                    model,
+                    inputs,
+                    outputs,
+                } => {
+                    let id = model.resource(results)?;
+                    self.clone()
+                        .tf_session_run(ctx, id, inputs, outputs)
+                        .await
+                        .map(Output::Tensors)
+                }
+                Operation::TfSessionUnload(model) => {
+                    let id = model.resource(results)?;
+                    self.clone().tf_session_unload(ctx, id).await?;
+                    Ok(Output::Done)
+                }
+            }
+        }
+        .instrument(span)
+        .await
+    }
+}
+
+impl Drop for ConnectionState {
+    /// Reclaim everything the client did not clean up before going away
+    fn drop(&mut self) {
+        debug!(connection = self.id, peer = %self.peer, "Closing connection");
+        let state = &self.server.0;
+
+        self.unload_models(&self.server.plugins());
+
+        for resource_id in self.resources.iter() {
+            if let Some((_, resource)) = state.resources.remove(&*resource_id) {
+                self.release(QuotaKind::ResourceBytes, resource.size());
+            }
+        }
+
+        for session_id in self.sessions.iter() {
+            debug!(
+                connection = self.id,
+                session = *session_id,
+                "Reclaiming session"
+            );
+            if state.sessions.remove(&*session_id).is_some() {
+                self.release(QuotaKind::Sessions, 1);
+            }
+        }
+
+        state.connections.remove(&self.id);
+    }
+}
+
+/// Accounts for a request in flight, for as long as it is alive
+struct InFlightRequest(Server);
+
+impl InFlightRequest {
+    fn new(server: Server) -> Self {
+        server.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightRequest(server)
+    }
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        if self.0 .0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0 .0.idle.notify_waiters();
+        }
+    }
+}
+
+/// `timeout`, cut short to end by the deadline of the request
+fn until_deadline(ctx: &Context, timeout: Duration) -> Duration {
+    ctx.deadline
+        .duration_since(SystemTime::now())
+        .unwrap_or_default()
+        .min(timeout)
+}
+
+/// Response to `request` when it is refused with `error` before reaching
+/// its handler
+fn error_response(request: &VaccelAPIRequest, error: Error) -> VaccelAPIResponse {
+    match request {
+        VaccelAPIRequest::Hello { .. } => VaccelAPIResponse::Hello(Err(error)),
+        VaccelAPIRequest::Authenticate { .. } => VaccelAPIResponse::Authenticate(Err(error)),
+        VaccelAPIRequest::NewSession { .. } => VaccelAPIResponse::NewSession(Err(error)),
+        VaccelAPIRequest::DestroySession { .. } => VaccelAPIResponse::DestroySession(Err(error)),
+        VaccelAPIRequest::RegisterResource { .. } => {
+            VaccelAPIResponse::RegisterResource(Err(error))
+        }
+        VaccelAPIRequest::UnregisterResource { .. } => {
+            VaccelAPIResponse::UnregisterResource(Err(error))
+        }
+        VaccelAPIRequest::TfSessionLoad { .. } => VaccelAPIResponse::TfSessionLoad(Err(error)),
+        VaccelAPIRequest::TfSessionUnload { .. } => VaccelAPIResponse::TfSessionUnload(Err(error)),
+        VaccelAPIRequest::TfSessionRun { .. } => VaccelAPIResponse::TfSessionRun(Err(error)),
+        VaccelAPIRequest::Batch { .. } => VaccelAPIResponse::Batch(Err(error)),
+        VaccelAPIRequest::SubmitJob { .. } => VaccelAPIResponse::SubmitJob(Err(error)),
+        VaccelAPIRequest::PollJob { .. } => VaccelAPIResponse::PollJob(Err(error)),
+        VaccelAPIRequest::WaitJob { .. } => VaccelAPIResponse::WaitJob(Err(error)),
+        VaccelAPIRequest::CancelJob { .. } => VaccelAPIResponse::CancelJob(Err(error)),
+        VaccelAPIRequest::NextEvents { .. } => VaccelAPIResponse::NextEvents(Err(error)),
+        VaccelAPIRequest::Plugins { .. } => VaccelAPIResponse::Plugins(Err(error)),
+    }
+}
+
+/// Name of the RPC method of `request`, as reported in metrics
+fn request_method(request: &VaccelAPIRequest) -> &'static str {
+    match request {
+        VaccelAPIRequest::Hello { .. } => "hello",
+        VaccelAPIRequest::Authenticate { .. } => "authenticate",
+        VaccelAPIRequest::NewSession { .. } => "new_session",
+        VaccelAPIRequest::DestroySession { .. } => "destroy_session",
+        VaccelAPIRequest::RegisterResource { .. } => "register_resource",
+        VaccelAPIRequest::UnregisterResource { .. } => "unregister_resource",
+        VaccelAPIRequest::TfSessionLoad { .. } => "tf_session_load",
+        VaccelAPIRequest::TfSessionUnload { .. } => "tf_session_unload",
+        VaccelAPIRequest::TfSessionRun { .. } => "tf_session_run",
+        VaccelAPIRequest::Batch { .. } => "batch",
+        VaccelAPIRequest::SubmitJob { .. } => "submit_job",
+        VaccelAPIRequest::PollJob { .. } => "poll_job",
+        VaccelAPIRequest::WaitJob { .. } => "wait_job",
+        VaccelAPIRequest::CancelJob { .. } => "cancel_job",
+        VaccelAPIRequest::NextEvents { .. } => "next_events",
+        VaccelAPIRequest::Plugins { .. } => "plugins",
+    }
+}
+
+/// The error `response` carries, if any
+fn response_error(response: &VaccelAPIResponse) -> Option<&Error> {
+    match response {
+        VaccelAPIResponse::Hello(res) => res.as_ref().err(),
+        VaccelAPIResponse::Authenticate(res) => res.as_ref().err(),
+        VaccelAPIResponse::NewSession(res) => res.as_ref().err(),
+        VaccelAPIResponse::DestroySession(res) => res.as_ref().err(),
+        VaccelAPIResponse::RegisterResource(res) => res.as_ref().err(),
+        VaccelAPIResponse::UnregisterResource(res) => res.as_ref().err(),
+        VaccelAPIResponse::TfSessionLoad(res) => res.as_ref().err(),
+        VaccelAPIResponse::TfSessionUnload(res) => res.as_ref().err(),
+        VaccelAPIResponse::TfSessionRun(res) => res.as_ref().err(),
+        VaccelAPIResponse::Batch(res) => res.as_ref().err(),
+        VaccelAPIResponse::SubmitJob(res) => res.as_ref().err(),
+        VaccelAPIResponse::PollJob(res) => res.as_ref().err(),
+        VaccelAPIResponse::WaitJob(res) => res.as_ref().err(),
+        VaccelAPIResponse::CancelJob(res) => res.as_ref().err(),
+        VaccelAPIResponse::NextEvents(res) => res.as_ref().err(),
+        VaccelAPIResponse::Plugins(res) => res.as_ref().err(),
+    }
+}
+
+/// Fail early if the client is no longer waiting for the response, so that
+/// we do not dispatch work to the plugins for nothing
+fn check_deadline(ctx: &Context) -> Result<()> {
+    if ctx.deadline <= SystemTime::now() {
+        return Err(Error::DeadlineExceeded);
+    }
+
+    Ok(())
 }
 
 #[tarpc::server]
-impl VaccelAPI for Server {
+impl VaccelAPI for Connection {
+    async fn hello(self, _: Context, client: Hello) -> Result<Hello> {
+        if client.version != PROTOCOL_VERSION {
+            warn!(
+                "Client speaks protocol version {}, we speak {}",
+                client.version, PROTOCOL_VERSION
+            );
+            return Err(Error::Incompatible {
+                client: client.version,
+                server: PROTOCOL_VERSION,
+            });
+        }
+
+        let mut features: Vec<String> = FEATURES.iter().map(|f| f.to_string()).collect();
+        if self.server().0.tokens.is_some() {
+            features.push(FEATURE_AUTH.to_string());
+        }
+
+        Ok(Hello {
+            version: PROTOCOL_VERSION,
+            features,
+        })
+    }
+
+    async fn authenticate(self, _: Context, token: String) -> Result<Tenant> {
+        let tokens = match self.server().0.tokens {
+            None => return Ok(Tenant::anonymous()),
+            Some(ref tokens) => tokens,
+        };
+
+        let tenant = match tokens.authenticate(&token) {
+            None => {
+                warn!("Authentication failed");
+                return Err(Error::Unauthenticated);
+            }
+            Some(tenant) => tenant.clone(),
+        };
+
+        if self.0.peer != Peer::Local {
+            self.charge_to(Principal::Tenant(tenant.name().to_string()))?;
+        }
+
+        debug!(tenant = tenant.name(), "Authenticated");
+        *self.0.tenant.write().unwrap() = Some(tenant.clone());
+
+        Ok(tenant)
+    }
+
     async fn new_session(self, _: Context) -> Result<u64> {
-        let id = self.next_id();
-        let mut rundir = self.0.rundir.as_path().to_path_buf();
+        self.authorize(Permission::Sessions)?;
+
+        self.charge(QuotaKind::Sessions, 1)?;
+
+        let server = self.server();
+        let id = server.next_id();
+        let mut rundir = server.0.rundir.as_path().to_path_buf();
 
         rundir.push(format!("session.{}", id));
-        fs::create_dir(&rundir)?;
+        if let Err(e) = fs::create_dir(&rundir) {
+            self.0.release(QuotaKind::Sessions, 1);
+            return Err(e.into());
+        }
 
         let session = Session::new()
             .with_id(id)
+            .with_owner(self.id())
             .with_rundir(rundir.as_path().to_path_buf());
-        self.0.sessions.insert(id, Arc::new(session));
+        server.0.sessions.insert(id, Arc::new(session));
+        self.0.sessions.insert(id);
+        Span::current().record("session", id);
 
         Ok(id)
     }
 
     async fn destroy_session(self, _: Context, session_id: u64) -> Result<()> {
-        match self.remove_session(&session_id) {
-            None => Err(Error::InvalidArgument),
-            Some(_) => Ok(()),
+        self.authorize(Permission::Sessions)?;
+        Span::current().record("session", session_id);
+        self.0.remove_session(session_id)
+    }
+
+    async fn register_resource(self, _: Context, resource: Resource) -> Result<u64> {
+        self.authorize(Permission::Resources)?;
+
+        // The files of path-backed resources are not accounted for, so they
+        // would escape a byte quota
+        let bounded = self
+            .account()
+            .is_some_and(|account| account.quota().max_resource_bytes.is_some());
+        if bounded && resource.is_path_backed() {
+            warn!("Refusing path-backed resource under a byte quota");
+            return Err(Error::QuotaExceeded(QuotaKind::ResourceBytes));
         }
+        self.charge(QuotaKind::ResourceBytes, resource.size())?;
+
+        let server = self.server();
+        let id = server.next_resource_id();
+        server.0.resources.insert(id, Arc::new(resource));
+        self.0.resources.insert(id);
+
+        Ok(id)
     }
 
-    async fn register_resource(self, _: Context, _resource: Resource) -> u64 {
-        todo!()
+    async fn unregister_resource(self, _: Context, resource_id: u64) -> Result<()> {
+        self.authorize(Permission::Resources)?;
+        if self.0.models.contains(&resource_id) {
+            return Err(Error::InvalidArgument);
+        }
+        if self.0.resources.remove(&resource_id).is_none() {
+            return Err(Error::InvalidArgument);
+        }
+
+        if let Some((_, resource)) = self.server().0.resources.remove(&resource_id) {
+            self.0.release(QuotaKind::ResourceBytes, resource.size());
+        }
+
+        Ok(())
     }
 
-    async fn tf_session_load(self, _: Context, _model_id: u64) -> Result<()> {
-        self.0
-            .plugins
-            .tf_session_load(_model_id)
+    async fn tf_session_load(self, ctx: Context, model_id: u64) -> Result<()> {
+        Span::current().record("model", model_id);
+        self.authorize(Permission::Tensorflow)?;
+        self.resource(model_id)?;
+
+        // Loading a model again does not take up another slot
+        let loaded = self.0.models.contains(&model_id);
+        if !loaded {
+            self.charge(QuotaKind::LoadedModels, 1)?;
+        }
+
+        let res = check_deadline(&ctx).and_then(|_| {
+            self.server()
+                .plugins()
+                .tf_session_load(model_id)
+                .map_err(|e| Error::Plugin(e.to_string()))
+        });
+        match res {
+            // Somebody else may have loaded it meanwhile, taking the slot
+            Ok(()) => {
+                if !self.0.models.insert(model_id) && !loaded {
+                    self.0.release(QuotaKind::LoadedModels, 1);
+                }
+            }
+            Err(_) if !loaded => self.0.release(QuotaKind::LoadedModels, 1),
+            Err(_) => {}
+        }
+
+        res
+    }
+
+    async fn tf_session_unload(self, ctx: Context, model_id: u64) -> Result<()> {
+        Span::current().record("model", model_id);
+        self.authorize(Permission::Tensorflow)?;
+        if !self.0.models.contains(&model_id) {
+            return Err(Error::InvalidArgument);
+        }
+
+        check_deadline(&ctx)?;
+        self.server()
+            .plugins()
+            .tf_session_unload(model_id)
+            .map_err(|e| Error::Plugin(e.to_string()))?;
+        if self.0.models.remove(&model_id).is_some() {
+            self.0.release(QuotaKind::LoadedModels, 1);
+        }
+
+        Ok(())
+    }
+
+    async fn tf_session_run(
+        self,
+        ctx: Context,
+        model_id: u64,
+        inputs: Vec<(Node, Tensor)>,
+        outputs: Vec<Node>,
+    ) -> Result<Vec<Tensor>> {
+        Span::current().record("model", model_id);
+        self.authorize(Permission::Tensorflow)?;
+        if !self.0.models.contains(&model_id) {
+            return Err(Error::InvalidArgument);
+        }
+
+        check_deadline(&ctx)?;
+        self.server()
+            .plugins()
+            .tf_session_run(model_id, &inputs, &outputs)
             .map_err(|e| Error::Plugin(e.to_string()))
     }
 
-    async fn tf_session_unload(self, _: Context, _model_id: u64) -> Result<()> {
-        todo!()
+    async fn batch(
+        self,
+        ctx: Context,
+        operations: Vec<Operation>,
+        stop_on_error: bool,
+    ) -> Result<Vec<Result<Output>>> {
+        let mut results = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            let res = self.operation(ctx, operation, &results).await;
+            let failed = res.is_err();
+            results.push(res);
+
+            if failed && stop_on_error {
+                break;
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn submit_job(self, _: Context, session_id: u64, operation: Operation) -> Result<u64> {
+        Span::current().record("session", session_id);
+        if !self.0.sessions.contains(&session_id) {
+            return Err(Error::InvalidArgument);
+        }
+        if self.0.jobs.len() >= MAX_JOBS {
+            return Err(Error::Busy);
+        }
+        let call = self.account().map(Call::new).transpose()?;
+        // Shutting down waits for jobs, as for requests
+        let request = InFlightRequest::new(self.server().clone());
+
+        let id = self.0.job_id.fetch_add(1, Ordering::SeqCst);
+        let (status, receiver) = watch::channel(JobStatus::Running);
+        let cancellation = CancellationToken::new();
+
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + JOB_DEADLINE;
+        let connection = Arc::downgrade(&self.0);
+        let cancelled = cancellation.clone();
+        let runtime = Handle::current();
+        let span = info_span!(parent: Span::current(), "job", job = id);
+
+        // Plugin calls block, so run the job on a thread of its own rather
+        // than on the runtime workers serving requests
+        tokio::task::spawn_blocking(move || {
+            let _span = span.entered();
+            let res = match connection.upgrade().map(Connection) {
+                // The client went away before the job got to run
+                None => JobStatus::Cancelled,
+                Some(connection) => runtime.block_on(async {
+                    tokio::select! {
+                        biased;
+                        _ = cancelled.cancelled() => JobStatus::Cancelled,
+                        res = connection.operation(ctx, operation, &[]) => JobStatus::Finished(res),
+                    }
+                }),
+            };
+            drop(call);
+            debug!("Job done");
+            let finished = !res.is_running() && !matches!(res, JobStatus::Cancelled);
+            let _ = status.send(res);
+            if let Some(connection) = connection.upgrade().filter(|_| finished) {
+                connection.push_event(Event::JobFinished {
+                    session: session_id,
+                    job: id,
+                });
+            }
+            drop(request);
+        });
+
+        self.0.jobs.insert(
+            id,
+            Job {
+                session: session_id,
+                status: receiver,
+                cancellation,
+            },
+        );
+
+        Ok(id)
+    }
+
+    async fn poll_job(self, _: Context, job_id: u64) -> Result<JobStatus> {
+        let status = match self.0.jobs.get(&job_id) {
+            None => return Err(Error::InvalidArgument),
+            Some(job) => {
+                Span::current().record("session", job.session);
+                job.status.borrow().clone()
+            }
+        };
+
+        Ok(self.0.report_job(job_id, status))
+    }
+
+    async fn wait_job(self, ctx: Context, job_id: u64, timeout: Duration) -> Result<JobStatus> {
+        let mut status = match self.0.jobs.get(&job_id) {
+            None => return Err(Error::InvalidArgument),
+            Some(job) => {
+                Span::current().record("session", job.session);
+                job.status.clone()
+            }
+        };
+
+        let done = async {
+            while status.borrow().is_running() {
+                if status.changed().await.is_err() {
+                    break;
+                }
+            }
+        };
+        tokio::select! {
+            _ = tokio::time::timeout(until_deadline(&ctx, timeout), done) => {}
+            _ = self.server().0.shutting_down.cancelled() => {}
+        }
+
+        let status = status.borrow().clone();
+        Ok(self.0.report_job(job_id, status))
+    }
+
+    async fn cancel_job(self, _: Context, job_id: u64) -> Result<()> {
+        match self.0.jobs.remove(&job_id) {
+            None => Err(Error::InvalidArgument),
+            Some((_, job)) => {
+                Span::current().record("session", job.session);
+                job.cancellation.cancel();
+                Ok(())
+            }
+        }
+    }
+
+    async fn next_events(self, ctx: Context, timeout: Duration) -> Result<Vec<Event>> {
+        self.authenticated()?;
+
+        let take = || {
+            let mut events = self.0.events.lock().unwrap();
+            events
+                .get_or_insert_with(VecDeque::new)
+                .drain(..)
+                .collect::<Vec<_>>()
+        };
+
+        let events = take();
+        if !events.is_empty() || timeout == Duration::from_secs(0) {
+            return Ok(events);
+        }
+
+        tokio::select! {
+            _ = tokio::time::timeout(until_deadline(&ctx, timeout), self.0.events_ready.notified()) => {}
+            _ = self.server().0.shutting_down.cancelled() => {}
+        }
+        Ok(take())
+    }
+
+    async fn plugins(self, _: Context) -> Result<Vec<Plugin>> {
+        self.authenticated()?;
+
+        // Where plugins are loaded from is only for administrators to see
+        Ok(self
+            .server()
+            .plugin_info()
+            .into_iter()
+            .map(|plugin| Plugin {
+                name: plugin.name,
+                functions: plugin.functions,
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use tarpc::context;
+
+    use crate::batch::Ref;
+    use crate::quota::Quota;
+    use crate::tensorflow::models::TensorflowSavedModelBuilder;
+
+    #[tokio::test]
+    async fn connections_share_server_state() {
+        let server = Server::new().expect("Could not create Server");
+
+        let first = server.connection(Peer::Local);
+        let second = server.connection(Peer::Local);
+        assert_ne!(first.id(), second.id());
+        assert_eq!(server.connections(), 2);
+
+        let a = first
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        let b = second
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        assert_ne!(a, b);
+
+        drop(first);
+        assert_eq!(server.connections(), 1);
+    }
+
+    #[tokio::test]
+    async fn sessions_reclaimed_on_disconnect() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Local);
+
+        let id = connection
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        let rundir = server
+            .get_session(&id)
+            .and_then(|s| s.rundir().map(Path::to_path_buf))
+            .expect("Session has no rundir");
+        assert!(rundir.is_dir());
+
+        drop(connection);
+        assert!(server.get_session(&id).is_none());
+        assert!(!rundir.exists());
+    }
+
+    #[tokio::test]
+    async fn protocol_version_mismatch() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Local);
+
+        let hello = Hello {
+            version: PROTOCOL_VERSION + 1,
+            features: Vec::new(),
+        };
+        match connection.hello(context::current(), hello).await {
+            Err(Error::Incompatible { client, server }) => {
+                assert_eq!(client, PROTOCOL_VERSION + 1);
+                assert_eq!(server, PROTOCOL_VERSION);
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_skips_plugin() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Local);
+
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let model_id = connection
+            .clone()
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .expect("Could not register resource");
+
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() - Duration::from_secs(1);
+        match connection.clone().tf_session_load(ctx, model_id).await {
+            Err(Error::DeadlineExceeded) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        assert!(!server.metrics().contains("vaccel_plugin_calls_total"));
+        assert!(!server.resource_info()[0].loaded);
+    }
+
+    #[tokio::test]
+    async fn long_polls_end_by_deadline() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Local);
+
+        let mut ctx = context::current();
+        ctx.deadline = SystemTime::now() + Duration::from_millis(100);
+        let start = Instant::now();
+        let events = connection
+            .next_events(ctx, Duration::from_secs(60))
+            .await
+            .expect("Could not get events");
+        assert!(events.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn session_and_plugin_events() {
+        let server = Server::new().expect("Could not create Server");
+        let connection = server.connection(Peer::Local);
+        let other = server.connection(Peer::Local);
+
+        for connection in [connection.clone(), other.clone()] {
+            let events = connection
+                .next_events(context::current(), Duration::from_secs(0))
+                .await
+                .expect("Could not subscribe");
+            assert!(events.is_empty());
+        }
+
+        let id = connection
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        server.reap_session(id).expect("Could not reap session");
+        assert!(server.get_session(&id).is_none());
+        server.reload_plugins().expect("Could not reload plugins");
+
+        let events = connection
+            .clone()
+            .next_events(context::current(), Duration::from_secs(1))
+            .await
+            .expect("Could not get events");
+        assert_eq!(
+            events,
+            vec![
+                Event::SessionReaped { session: id },
+                Event::PluginReloaded {
+                    name: "vaccel-noop".to_string()
+                },
+            ]
+        );
+
+        // Session events are only delivered to the owner of the session
+        let events = other
+            .next_events(context::current(), Duration::from_secs(1))
+            .await
+            .expect("Could not get events");
+        assert_eq!(
+            events,
+            vec![Event::PluginReloaded {
+                name: "vaccel-noop".to_string()
+            }]
+        );
+    }
+
+    /// A client served by `server` over an in-memory transport
+    fn local_client(server: &Server) -> VaccelAPIClient {
+        let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+        tokio::spawn(server.clone().serve(server_transport, Peer::Local));
+        VaccelAPIClient::new(tarpc::client::Config::default(), client_transport).spawn()
+    }
+
+    #[tokio::test]
+    async fn connection_and_request_limits() {
+        let server = ServerBuilder::new()
+            .limits(Limits {
+                max_connections: Some(1),
+                ..Default::default()
+            })
+            .build()
+            .expect("Could not create Server");
+
+        let first = local_client(&server);
+        first
+            .new_session(context::current())
+            .await
+            .unwrap()
+            .expect("Could not create session");
+
+        // Clients beyond the limit are told so, then hung up on
+        let second = local_client(&server);
+        match second.new_session(context::current()).await.unwrap() {
+            Err(Error::Busy) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(second.new_session(context::current()).await.is_err());
+
+        // Closing a connection makes room for another
+        drop(first);
+        let mut third = local_client(&server);
+        while third
+            .new_session(context::current())
+            .await
+            .unwrap()
+            .is_err()
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            third = local_client(&server);
+        }
+
+        for limits in &[
+            Limits {
+                max_requests: Some(0),
+                ..Default::default()
+            },
+            Limits {
+                max_requests_per_connection: Some(0),
+                ..Default::default()
+            },
+        ] {
+            let server = ServerBuilder::new()
+                .limits(limits.clone())
+                .build()
+                .expect("Could not create Server");
+            let client = local_client(&server);
+
+            match client.new_session(context::current()).await.unwrap() {
+                Err(Error::Busy) => {}
+                res => panic!("Unexpected result with {:?}: {:?}", limits, res),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_cleans_up() {
+        let server = Server::new().expect("Could not create Server");
+        let client = local_client(&server);
+
+        let id = client
+            .new_session(context::current())
+            .await
+            .unwrap()
+            .expect("Could not create session");
+        let rundir = server
+            .get_session(&id)
+            .and_then(|s| s.rundir().map(Path::to_path_buf))
+            .expect("Session has no rundir");
+        let model = TensorflowSavedModelBuilder::new()
+            .model(vec![0; 4])
+            .checkpoint(vec![0; 4])
+            .var_index(vec![0; 4])
+            .build()
+            .unwrap();
+        let model_id = client
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .unwrap()
+            .expect("Could not register resource");
+        client
+            .tf_session_load(context::current(), model_id)
+            .await
+            .unwrap()
+            .expect("Could not load model");
+
+        // Clients waiting for events do not hold the shutdown up
+        client
+            .next_events(context::current(), Duration::from_secs(0))
+            .await
+            .unwrap()
+            .expect("Could not subscribe");
+        let poll = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .next_events(context::current(), Duration::from_secs(5))
+                    .await
+            }
+        });
+        while server.0.in_flight.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        // Jobs still running are waited for, and what they load is unloaded
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let job_model = client
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .unwrap()
+            .expect("Could not register resource");
+        client
+            .submit_job(
+                context::current(),
+                id,
+                Operation::TfSessionLoad(Ref::Id(job_model)),
+            )
+            .await
+            .unwrap()
+            .expect("Could not submit job");
+
+        let start = Instant::now();
+        server.shutdown(Duration::from_secs(30)).await;
+        assert!(start.elapsed() < Duration::from_secs(5));
+        // The job may have finished before the shutdown
+        let events = poll.await.unwrap().unwrap().unwrap();
+        assert!(events
+            .iter()
+            .all(|event| matches!(event, Event::JobFinished { .. })));
+        for connection in server.live_connections() {
+            assert!(connection.models.is_empty());
+        }
+        assert!(server.get_session(&id).is_none());
+        assert!(!rundir.exists());
+        assert!(!server.0.rundir.as_path().exists());
+
+        match client.new_session(context::current()).await.unwrap() {
+            Err(Error::Busy) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[tokio::test]
+    async fn rpc_and_plugin_metrics() {
+        let server = Server::new().expect("Could not create Server");
+        let client = local_client(&server);
+
+        client
+            .new_session(context::current())
+            .await
+            .unwrap()
+            .expect("Could not create session");
+        let model = TensorflowSavedModelBuilder::new()
+            .model(vec![0; 16])
+            .checkpoint(vec![0; 8])
+            .var_index(vec![0; 8])
+            .build()
+            .unwrap();
+        let model_id = client
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .unwrap()
+            .expect("Could not register resource");
+        client
+            .tf_session_load(context::current(), model_id)
+            .await
+            .unwrap()
+            .expect("Could not load model");
+        assert!(client
+            .destroy_session(context::current(), 1234)
+            .await
+            .unwrap()
+            .is_err());
+
+        let metrics = server.metrics();
+        for line in [
+            r#"vaccel_rpc_calls_total{method="new_session"} 1"#,
+            r#"vaccel_rpc_errors_total{kind="invalid_argument",method="destroy_session"} 1"#,
+            r#"vaccel_rpc_duration_seconds_count{method="tf_session_load"} 1"#,
+            r#"vaccel_plugin_calls_total{function="TFSessionLoad",plugin="vaccel-noop"} 1"#,
+            "vaccel_sessions 1",
+            "vaccel_resource_bytes 32",
+            "vaccel_connections 1",
+        ] {
+            assert!(metrics.contains(line), "Missing {} in:\n{}", line, metrics);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_spans() {
+        let logs = Arc::new(Mutex::new(Vec::new()));
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(tracing::Level::DEBUG)
+            .with_writer(move || LogWriter(writer.clone()))
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = Server::new().expect("Could not create Server");
+        let client = local_client(&server);
+        let model = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        let model_id = client
+            .register_resource(context::current(), Resource::TensorflowSavedModel(model))
+            .await
+            .unwrap()
+            .expect("Could not register resource");
+        let ctx = context::current();
+        client
+            .tf_session_load(ctx, model_id)
+            .await
+            .unwrap()
+            .expect("Could not load model");
+
+        let logs = String::from_utf8(logs.lock().unwrap().clone()).unwrap();
+        let call = logs
+            .lines()
+            .find(|line| line.contains("Calling plugin"))
+            .expect("Plugin call not logged");
+        for field in [
+            r#""name":"rpc""#.to_string(),
+            r#""connection":1"#.to_string(),
+            r#""method":"tf_session_load""#.to_string(),
+            format!(r#""request":"{}""#, ctx.trace_id()),
+            format!(r#""model":{}"#, model_id),
+            r#""name":"plugin""#.to_string(),
+            r#""function":"TFSessionLoad""#.to_string(),
+        ] {
+            assert!(call.contains(&field), "Missing {} in {}", field, call);
+        }
+    }
+
+    struct LogWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn tenant_quotas() {
+        let quota = Quota {
+            max_sessions: Some(1),
+            max_resource_bytes: Some(12),
+            max_loaded_models: Some(1),
+            max_concurrent_calls: None,
+        };
+        let server = ServerBuilder::new()
+            .quotas(Quotas::new(quota))
+            .build()
+            .expect("Could not create Server");
+        let model = || {
+            let model = TensorflowSavedModelBuilder::new()
+                .model(vec![0; 4])
+                .checkpoint(vec![0; 4])
+                .var_index(vec![0; 4])
+                .build()
+                .unwrap();
+            Resource::TensorflowSavedModel(model)
+        };
+
+        let first = server.connection(Peer::Vsock { cid: 3 });
+        let session = first
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+        let model_id = first
+            .clone()
+            .register_resource(context::current(), model())
+            .await
+            .expect("Could not register resource");
+        first
+            .clone()
+            .tf_session_load(context::current(), model_id)
+            .await
+            .expect("Could not load model");
+
+        // Connections from the same guest share its quota
+        let second = server.connection(Peer::Vsock { cid: 3 });
+        match second.clone().new_session(context::current()).await {
+            Err(Error::QuotaExceeded(QuotaKind::Sessions)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match second
+            .clone()
+            .register_resource(context::current(), model())
+            .await
+        {
+            Err(Error::QuotaExceeded(QuotaKind::ResourceBytes)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Other guests and in-process clients are accounted separately
+        let other = server.connection(Peer::Vsock { cid: 4 });
+        other
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+
+        // The files of path-backed resources would escape the byte quota
+        let path = TensorflowSavedModelBuilder::new()
+            .export_dir(PathBuf::from("/tmp/model"))
+            .build()
+            .unwrap();
+        match other
+            .register_resource(context::current(), Resource::TensorflowSavedModel(path))
+            .await
+        {
+            Err(Error::QuotaExceeded(QuotaKind::ResourceBytes)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let local = server.connection(Peer::Local);
+        for _ in 0..2 {
+            local
+                .clone()
+                .new_session(context::current())
+                .await
+                .expect("Could not create session");
+        }
+
+        first
+            .clone()
+            .destroy_session(context::current(), session)
+            .await
+            .expect("Could not destroy session");
+        second
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+
+        let usage = |cid| {
+            server
+                .account_info()
+                .into_iter()
+                .find(|account| account.principal == Principal::Cid(cid))
+                .unwrap()
+                .usage
+        };
+        assert_eq!(usage(3).resource_bytes, 12);
+        assert_eq!(usage(3).loaded_models, 1);
+
+        drop(first);
+        assert_eq!(usage(3).resource_bytes, 0);
+        assert_eq!(usage(3).loaded_models, 0);
+        assert_eq!(usage(3).sessions, 1);
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_quota() {
+        let quota = Quota {
+            max_concurrent_calls: Some(1),
+            ..Quota::default()
+        };
+        let server = ServerBuilder::new()
+            .quotas(Quotas::new(quota))
+            .build()
+            .expect("Could not create Server");
+        let (client_transport, server_transport) = tarpc::transport::channel::unbounded();
+        tokio::spawn(
+            server
+                .clone()
+                .serve(server_transport, Peer::Vsock { cid: 3 }),
+        );
+        let client =
+            VaccelAPIClient::new(tarpc::client::Config::default(), client_transport).spawn();
+
+        // Clients waiting for events do not take up a call
+        client
+            .next_events(context::current(), Duration::from_secs(0))
+            .await
+            .unwrap()
+            .expect("Could not subscribe");
+        let poll = tokio::spawn({
+            let client = client.clone();
+            async move {
+                client
+                    .next_events(context::current(), Duration::from_secs(1))
+                    .await
+            }
+        });
+        while server.0.in_flight.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        let session = client
+            .new_session(context::current())
+            .await
+            .unwrap()
+            .expect("Could not create session");
+
+        // Jobs take up a call for as long as they run
+        let job = client
+            .submit_job(context::current(), session, Operation::NewSession)
+            .await
+            .unwrap()
+            .expect("Could not submit job");
+        client
+            .wait_job(context::current(), job, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .expect("Could not wait for job");
+        let account = &server.account_info()[0];
+        assert_eq!(account.usage.concurrent_calls, 0);
+
+        poll.await.unwrap().unwrap().expect("Could not poll events");
+    }
+
+    #[tokio::test]
+    async fn token_authentication() {
+        let tokens = "secret guest sessions\n".parse().unwrap();
+        let server = ServerBuilder::new()
+            .tokens(tokens)
+            .build()
+            .expect("Could not create Server");
+        let connection = server.connection(Peer::Vsock { cid: 3 });
+
+        match connection.clone().new_session(context::current()).await {
+            Err(Error::Unauthenticated) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        match connection
+            .clone()
+            .authenticate(context::current(), "wrong".to_string())
+            .await
+        {
+            Err(Error::Unauthenticated) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let tenant = connection
+            .clone()
+            .authenticate(context::current(), "secret".to_string())
+            .await
+            .expect("Could not authenticate");
+        assert_eq!(tenant.name(), "guest");
+
+        connection
+            .clone()
+            .new_session(context::current())
+            .await
+            .expect("Could not create session");
+
+        match connection
+            .clone()
+            .tf_session_unload(context::current(), 1)
+            .await
+        {
+            Err(Error::PermissionDenied) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Listing plugins and subscribing to events only take authenticating
+        let anonymous = server.connection(Peer::Vsock { cid: 4 });
+        match anonymous.clone().plugins(context::current()).await {
+            Err(Error::Unauthenticated) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match anonymous
+            .next_events(context::current(), Duration::from_secs(0))
+            .await
+        {
+            Err(Error::Unauthenticated) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let plugins = connection
+            .clone()
+            .plugins(context::current())
+            .await
+            .expect("Could not list plugins");
+        assert_eq!(plugins[0].name, "vaccel-noop");
+        connection
+            .next_events(context::current(), Duration::from_secs(0))
+            .await
+            .expect("Could not subscribe to events");
     }
 }
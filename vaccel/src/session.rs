@@ -7,6 +7,8 @@ use log::debug;
 pub struct Session {
     /// Unique identifier of the session
     id: u64,
+    /// Connection that created the session
+    owner: Option<u64>,
     /// Rundir for the session
     rundir: Option<PathBuf>,
 }
@@ -21,6 +23,11 @@ impl Session {
         self
     }
 
+    pub(crate) fn with_owner(mut self, owner: u64) -> Self {
+        self.owner = Some(owner);
+        self
+    }
+
     pub(crate) fn with_rundir(mut self, rundir: PathBuf) -> Self {
         self.rundir = Some(rundir);
         self
@@ -30,10 +37,14 @@ impl Session {
         self.id
     }
 
+    pub fn owner(&self) -> Option<u64> {
+        self.owner
+    }
+
     pub fn rundir(&self) -> Option<&Path> {
         match &self.rundir {
             None => None,
-            Some(path) => Some(&path),
+            Some(path) => Some(path),
         }
     }
 }
@@ -42,7 +53,7 @@ impl Drop for Session {
     fn drop(&mut self) {
         debug!("Dropping session: {}", self.id());
         if let Some(ref rundir) = self.rundir {
-            let _ = std::fs::remove_dir(&rundir);
+            let _ = std::fs::remove_dir(rundir);
         }
     }
 }
@@ -1 +1,3 @@
 pub mod models;
+
+pub use vaccel_plugins::{DataType, Node, Tensor};
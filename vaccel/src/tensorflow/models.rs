@@ -4,20 +4,20 @@ use std::path::PathBuf;
 use crate::resource::ResourceType;
 use crate::{Error, Result};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 struct InMemorySavedModel {
     model: Vec<u8>,
     checkpoint: Vec<u8>,
     var_index: Vec<u8>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 enum SavedModel {
     ExportDir(PathBuf),
     InMemory(InMemorySavedModel),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct TensorflowSavedModel {
     id: u64,
     model: SavedModel,
@@ -81,15 +81,28 @@ impl ResourceType<'_> for TensorflowSavedModel {
     fn id(&self) -> u64 {
         self.id
     }
+
+    fn size(&self) -> usize {
+        match self.model {
+            SavedModel::ExportDir(_) => 0,
+            SavedModel::InMemory(ref model) => {
+                model.model.len() + model.checkpoint.len() + model.var_index.len()
+            }
+        }
+    }
+
+    fn is_path_backed(&self) -> bool {
+        matches!(self.model, SavedModel::ExportDir(_))
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 enum ProtobufModel {
     Protobuf(PathBuf),
     InMemory(Vec<u8>),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct TensorflowModel {
     id: u64,
     model: ProtobufModel,
@@ -99,4 +112,15 @@ impl ResourceType<'_> for TensorflowModel {
     fn id(&self) -> u64 {
         self.id
     }
+
+    fn size(&self) -> usize {
+        match self.model {
+            ProtobufModel::Protobuf(_) => 0,
+            ProtobufModel::InMemory(ref model) => model.len(),
+        }
+    }
+
+    fn is_path_backed(&self) -> bool {
+        matches!(self.model, ProtobufModel::Protobuf(_))
+    }
 }
@@ -0,0 +1,272 @@
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore, ServerConfig};
+use tokio_rustls::TlsConnector;
+
+use crate::{Error, Result};
+
+pub use tokio_rustls::TlsAcceptor;
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let pem = fs::read(path)?;
+    let certs = CertificateDer::pem_slice_iter(&pem)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::Tls(format!("{}: {}", path.display(), e)))?;
+
+    if certs.is_empty() {
+        return Err(Error::Tls(format!(
+            "{}: no certificates found",
+            path.display()
+        )));
+    }
+
+    Ok(certs)
+}
+
+fn load_key(path: &Path) -> Result<PrivateKeyDer<'static>> {
+    let pem = fs::read(path)?;
+    PrivateKeyDer::from_pem_slice(&pem)
+        .map_err(|e| Error::Tls(format!("{}: {}", path.display(), e)))
+}
+
+fn load_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(path)? {
+        roots
+            .add(cert)
+            .map_err(|e| Error::Tls(format!("{}: {}", path.display(), e)))?;
+    }
+
+    Ok(roots)
+}
+
+/// TLS settings used by a `Vaccel` client connecting over TCP
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM bundle with the certificate authorities trusted to sign the
+    /// agent's certificate
+    ca: PathBuf,
+    /// PEM certificate chain and private key presented to the agent, when
+    /// it requires client authentication
+    identity: Option<(PathBuf, PathBuf)>,
+    /// Name to verify the agent's certificate against. Defaults to the host
+    /// part of the agent address
+    server_name: Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new(ca: PathBuf) -> Self {
+        TlsConfig {
+            ca,
+            identity: None,
+            server_name: None,
+        }
+    }
+
+    pub fn identity(mut self, cert: PathBuf, key: PathBuf) -> Self {
+        self.identity = Some((cert, key));
+        self
+    }
+
+    pub fn server_name(mut self, name: String) -> Self {
+        self.server_name = Some(name);
+        self
+    }
+
+    pub(crate) fn connector(&self) -> Result<TlsConnector> {
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Tls(e.to_string()))?
+            .with_root_certificates(load_roots(&self.ca)?);
+
+        let config = match self.identity {
+            None => builder.with_no_client_auth(),
+            Some((ref cert, ref key)) => builder
+                .with_client_auth_cert(load_certs(cert)?, load_key(key)?)
+                .map_err(|e| Error::Tls(e.to_string()))?,
+        };
+
+        Ok(TlsConnector::from(Arc::new(config)))
+    }
+
+    /// Name to verify the agent's certificate against, when connecting to
+    /// `addr`
+    pub(crate) fn server_name_for(&self, addr: &str) -> Result<ServerName<'static>> {
+        let name = match self.server_name {
+            Some(ref name) => name.as_str(),
+            None => addr
+                .rsplit_once(':')
+                .map(|(host, _)| host)
+                .unwrap_or(addr)
+                .trim_start_matches('[')
+                .trim_end_matches(']'),
+        };
+
+        ServerName::try_from(name.to_string()).map_err(|e| Error::Tls(e.to_string()))
+    }
+}
+
+/// TLS settings used by the agent when listening on TCP
+#[derive(Debug, Clone)]
+pub struct ServerTlsConfig {
+    /// PEM certificate chain presented to clients
+    cert: PathBuf,
+    /// PEM private key of the certificate
+    key: PathBuf,
+    /// PEM bundle with the certificate authorities trusted to sign client
+    /// certificates. When set, clients must authenticate with a certificate
+    client_ca: Option<PathBuf>,
+}
+
+impl ServerTlsConfig {
+    pub fn new(cert: PathBuf, key: PathBuf) -> Self {
+        ServerTlsConfig {
+            cert,
+            key,
+            client_ca: None,
+        }
+    }
+
+    pub fn client_ca(mut self, ca: PathBuf) -> Self {
+        self.client_ca = Some(ca);
+        self
+    }
+
+    pub fn acceptor(&self) -> Result<TlsAcceptor> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| Error::Tls(e.to_string()))?;
+
+        let builder = match self.client_ca {
+            None => builder.with_no_client_auth(),
+            Some(ref ca) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(
+                    Arc::new(load_roots(ca)?),
+                    provider,
+                )
+                .build()
+                .map_err(|e| Error::Tls(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+        };
+
+        let config = builder
+            .with_single_cert(load_certs(&self.cert)?, load_key(&self.key)?)
+            .map_err(|e| Error::Tls(e.to_string()))?;
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use tarpc::serde_transport;
+    use tarpc::server::{BaseChannel, Channel};
+    use tokio::net::TcpListener;
+    use tokio_serde::formats::Json;
+    use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+    use crate::client::{Endpoint, Vaccel, VaccelConfig};
+    use crate::server::{Peer, Server, VaccelAPI};
+
+    /// Write a CA and certificates signed by it for `localhost` and for a
+    /// client into `dir`
+    fn generate_certs(dir: &Path) {
+        let mut params = CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        fs::write(dir.join("ca.pem"), ca.as_ref().pem()).unwrap();
+
+        for (name, san) in &[("server", "localhost"), ("client", "client")] {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![san.to_string()])
+                .unwrap()
+                .signed_by(&key, &ca)
+                .unwrap();
+            fs::write(dir.join(format!("{}.pem", name)), cert.pem()).unwrap();
+            fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+        }
+    }
+
+    async fn serve_tls(dir: &Path) -> String {
+        let acceptor = ServerTlsConfig::new(dir.join("server.pem"), dir.join("server.key"))
+            .client_ca(dir.join("ca.pem"))
+            .acceptor()
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server = Server::new().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((stream, addr)) = listener.accept().await {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    let transport = serde_transport::new(
+                        Framed::new(stream, LengthDelimitedCodec::new()),
+                        Json::default(),
+                    );
+                    tokio::spawn(
+                        BaseChannel::with_defaults(transport)
+                            .execute(server.connection(Peer::Tcp(addr)).serve()),
+                    );
+                }
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn mutual_tls() {
+        let dir = mktemp::Temp::new_dir().unwrap();
+        generate_certs(&dir);
+        let addr = serve_tls(&dir).await;
+
+        let tls = TlsConfig::new(dir.join("ca.pem")).server_name("localhost".to_string());
+        let client = Vaccel::new(
+            VaccelConfig::new(Endpoint::Tcp(addr.clone())).tls(
+                tls.clone()
+                    .identity(dir.join("client.pem"), dir.join("client.key")),
+            ),
+        )
+        .await
+        .expect("Could not connect with a client certificate");
+        client
+            .new_session()
+            .await
+            .expect("Could not create session");
+
+        // The agent requires a client certificate
+        let res = match Vaccel::new(VaccelConfig::new(Endpoint::Tcp(addr)).tls(tls)).await {
+            Ok(client) => client.new_session().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        assert!(res.is_err());
+    }
+
+    #[tokio::test]
+    async fn tls_requires_tcp_endpoint() {
+        // Only TCP connections are encrypted, so the client refuses to
+        // silently go without
+        let tls = TlsConfig::new(PathBuf::from("/tmp/ca.pem"));
+        for endpoint in [
+            Endpoint::Local,
+            Endpoint::Unix(PathBuf::from("/tmp/vaccel.sock")),
+        ] {
+            match Vaccel::new(VaccelConfig::new(endpoint).tls(tls.clone())).await {
+                Err(Error::InvalidArgument) => {}
+                res => panic!("Unexpected result: {:?}", res.map(|_| ())),
+            }
+        }
+    }
+}